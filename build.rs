@@ -33,9 +33,14 @@ fn main() {
     writeln!(config, "pub static PACKAGE: &str = {:?};", data["package"].as_string().unwrap()).unwrap();
     writeln!(config, "pub static BIN_DIR: &str = {:?};", data["bindir"].as_string().unwrap()).unwrap();
     writeln!(config, "pub static LIB_DIR: &str = {:?};", data["libdir"].as_string().unwrap()).unwrap();
+    writeln!(config, "pub static CONF_DIR: &str = {:?};", data["confdir"].as_string().unwrap()).unwrap();
+    writeln!(config, "pub static CRONTAB_GROUP: &str = {:?};", data["crontabgroup"].as_string().unwrap()).unwrap();
+    writeln!(config, "pub static UNIT_DIR: &str = {:?};", data["unitdir"].as_string().unwrap()).unwrap();
 
-    let mut data = Json::Object(data);
     let schedules = get_required_schedules();
+    writeln!(config, "pub static SCHEDULES: &[&str] = &{:?};", schedules).unwrap();
+
+    let mut data = Json::Object(data);
 
     for schedule in schedules.iter() {
         data.as_object_mut()
@@ -52,6 +57,11 @@ fn main() {
 
     data.as_object_mut().unwrap().insert("schedules".to_owned(), schedules.to_json());
 
+    // handlebars can't reach confdir from inside an each block
+    let confdir = data["confdir"].as_string().unwrap().to_owned();
+    let script_dirs = schedules.iter().map(|schedule| format!("{}/cron.{}", confdir, schedule)).collect::<Vec<_>>();
+    data.as_object_mut().unwrap().insert("scriptdirs".to_owned(), script_dirs.to_json());
+
     compile_templates(UNITS_DIR, output.join("units"), &data);
     compile_templates(MAN_DIR, output.join("man"), &data);
 }
@@ -121,6 +131,11 @@ fn build_render_data() -> BTreeMap<String, Json> {
         Json::String(env::var("UNIT_DIR").unwrap_or_else(|_| libdir.clone() + "/systemd/system")),
     );
 
+    ctx.insert(
+        "runparts".to_owned(),
+        Json::String(env::var("RUN_PARTS").unwrap_or_else(|_| format!("{}/{}/run-parts", libdir, package))),
+    );
+
     ctx.insert("libdir".to_owned(), Json::String(libdir));
     ctx.insert("datadir".to_owned(), Json::String(datadir));
     ctx.insert("prefix".to_owned(), Json::String(prefix));
@@ -131,8 +146,8 @@ fn build_render_data() -> BTreeMap<String, Json> {
     );
 
     ctx.insert(
-        "runparts".to_owned(),
-        Json::String(env::var("RUN_PARTS").unwrap_or_else(|_| "/usr/bin/run-parts".to_owned())),
+        "crontabgroup".to_owned(),
+        Json::String(env::var("CRONTAB_GROUP").unwrap_or_else(|_| "cron".to_owned())),
     );
 
     ctx.insert("persistent".to_owned(), Json::Boolean(env::var("CARGO_FEATURE_PERSISTENT").is_ok()));
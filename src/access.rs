@@ -0,0 +1,134 @@
+//! Access control of users crontabs with /etc/cron.allow and /etc/cron.deny.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::path::Path;
+
+use crate::sysroot;
+
+pub static CRON_ALLOW_FILE: &str = "/etc/cron.allow";
+pub static CRON_DENY_FILE: &str = "/etc/cron.deny";
+
+// one user name per line, blank lines and comments are ignored,
+// `None` if the file doesn't exist
+fn read_users(path: &Path) -> io::Result<Option<Vec<String>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut users = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let name = line.trim();
+        if !name.is_empty() && !name.starts_with('#') {
+            users.push(name.to_owned());
+        }
+    }
+    Ok(Some(users))
+}
+
+/// Returns the file which forbids the user to have a crontab, if any.
+///
+/// Root is always allowed. Otherwise, if cron.allow exists, only users
+/// listed there are allowed and cron.deny is ignored. If only cron.deny
+/// exists, users listed there are denied. Without any of them, everybody
+/// is allowed.
+pub fn denied_by(user: &str) -> io::Result<Option<&'static str>> {
+    denied_in(sysroot::get(), user)
+}
+
+// the files of the system mounted in root
+fn denied_in(root: &Path, user: &str) -> io::Result<Option<&'static str>> {
+    if user == "root" {
+        return Ok(None);
+    }
+
+    let path = |file: &str| root.join(file.trim_start_matches('/'));
+    if let Some(allowed) = read_users(&path(CRON_ALLOW_FILE))? {
+        return Ok(if allowed.iter().any(|name| name == user) {
+            None
+        } else {
+            Some(CRON_ALLOW_FILE)
+        });
+    }
+
+    if let Some(denied) = read_users(&path(CRON_DENY_FILE))? {
+        if denied.iter().any(|name| name == user) {
+            return Ok(Some(CRON_DENY_FILE));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{denied_in, CRON_ALLOW_FILE, CRON_DENY_FILE};
+    use crate::testing::temp_dir;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // a root with the given cron.allow and cron.deny, None for a missing file
+    fn root(name: &str, allow: Option<&str>, deny: Option<&str>) -> PathBuf {
+        let root = temp_dir(&format!("access-{}", name));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        for (file, content) in &[(CRON_ALLOW_FILE, allow), (CRON_DENY_FILE, deny)] {
+            if let Some(content) = content {
+                fs::write(root.join(file.trim_start_matches('/')), content).unwrap();
+            }
+        }
+        root
+    }
+
+    fn denied(root: &Path, user: &str) -> Option<&'static str> {
+        denied_in(root, user).unwrap()
+    }
+
+    #[test]
+    fn allow_wins_over_deny() {
+        let root = root("both", Some("alice\n"), Some("alice\nbob\n"));
+        assert_eq!(denied(&root, "alice"), None);
+        assert_eq!(denied(&root, "bob"), Some(CRON_ALLOW_FILE));
+        assert_eq!(denied(&root, "carol"), Some(CRON_ALLOW_FILE));
+        assert_eq!(denied(&root, "root"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn allow_only() {
+        // an empty cron.allow allows nobody but root
+        let root = root("allow", Some(""), None);
+        assert_eq!(denied(&root, "alice"), Some(CRON_ALLOW_FILE));
+        assert_eq!(denied(&root, "root"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn deny_only() {
+        let root = root("deny", None, Some("bob\nroot\n"));
+        assert_eq!(denied(&root, "alice"), None);
+        assert_eq!(denied(&root, "bob"), Some(CRON_DENY_FILE));
+        assert_eq!(denied(&root, "root"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn no_files() {
+        let root = root("none", None, None);
+        assert_eq!(denied(&root, "alice"), None);
+        assert_eq!(denied(&root, "root"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let root = root("comments", Some("# users of cron\n\n  alice  \n#bob\n\t\n"), None);
+        assert_eq!(denied(&root, "alice"), None);
+        assert_eq!(denied(&root, "bob"), Some(CRON_ALLOW_FILE));
+        assert_eq!(denied(&root, "#bob"), Some(CRON_ALLOW_FILE));
+        assert_eq!(denied(&root, ""), Some(CRON_ALLOW_FILE));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+extern crate cronparse;
+extern crate docopt;
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::metadata;
+use std::process::exit;
+use std::str::FromStr;
+
+use cronparse::crontab::{AnacrontabEntry, CrontabEntry, EnvVarEntry, SystemCrontabEntry, UserCrontabEntry};
+use cronparse::schedule::{Calendar, Period, Schedule};
+use cronparse::{CrontabFile, CrontabFileError};
+use docopt::Docopt;
+use systemd_crontab_generator::calendar::{with_timezone, CalendarEvent};
+use systemd_crontab_generator::schedule::{calendar_event, describe_calendar, describe_period, period_event, restricts_both_days, Options};
+use time::Timespec;
+
+static USAGE: &str = r#"
+Usage: cron-explain [-n <count>] [--tz <zone>] [--system | --anacron] <input>...
+       cron-explain -h | --help
+
+Explain crontab schedules the way systemd-crontab-generator translates them
+
+Each input is a crontab file, a whole crontab line, or just a schedule,
+like "*/7 9-17 * * 1-5" or "@daily". Quote lines and schedules, so that
+they are passed as a single argument.
+
+Options:
+
+  -h, --help                   Show this help message and exit.
+  -n <count>, --count <count>  Number of next elapse times to show [default: 5].
+  --tz <zone>                  Time zone to compute elapse times in, unless the
+                               job sets CRON_TZ. Defaults to the local time zone.
+  --system                     Inputs are in /etc/crontab format, with a user
+                               column before the command.
+  --anacron                    Inputs are in /etc/anacrontab format.
+"#;
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_input: Vec<String>,
+    flag_count: usize,
+    flag_tz: Option<String>,
+    flag_system: bool,
+    flag_anacron: bool,
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    time::at(Timespec::new(timestamp, 0))
+        .strftime("%a %Y-%m-%d %H:%M:%S %z")
+        .map(|t| t.to_string())
+        .unwrap_or_default()
+}
+
+fn explain(title: &str, period: Option<&Period>, calendar: Option<&Calendar>, env: &BTreeMap<String, String>, args: &Args) {
+    let options = Options::from_env(env);
+
+    println!("{}", title);
+    explain_event(period, calendar, &options, args);
+    println!();
+}
+
+fn explain_event(period: Option<&Period>, calendar: Option<&Calendar>, options: &Options, args: &Args) {
+    let event = match (period, calendar) {
+        (Some(&Period::Reboot), _) => {
+            println!("  OnBootSec={}m", if options.delay == 0 { 1 } else { options.delay });
+            println!("  Runs {}", describe_period(&Period::Reboot));
+            return;
+        }
+        (Some(period), _) => {
+            println!("  Runs {}", describe_period(period));
+            period_event(period, options).unwrap_or_default()
+        }
+        (None, Some(calendar)) => {
+            println!("  Runs {}", describe_calendar(calendar));
+            if restricts_both_days(calendar) {
+                println!("  Note: cron would run it when either day-of-month or day-of-week matches");
+            }
+            calendar_event(calendar, options)
+        }
+        (None, None) => return,
+    };
+    let event_str = event.trim();
+    println!("  OnCalendar={}", event_str);
+
+    let event = match event_str.parse::<CalendarEvent>() {
+        Ok(event) => event,
+        Err(err) => {
+            println!("  {}", err);
+            return;
+        }
+    };
+
+    let timezone = event.timezone.as_ref().or(args.flag_tz.as_ref()).map(|tz| &**tz);
+    with_timezone(timezone, || {
+        let elapses = event.elapses(time::get_time().sec, args.flag_count);
+        if elapses.is_empty() {
+            println!("  Never elapses");
+            return;
+        }
+
+        println!("  Next elapses ({}):", timezone.unwrap_or("local time"));
+        for elapse in elapses {
+            println!("    {}", format_timestamp(elapse));
+        }
+    })
+}
+
+fn explain_entry(entry: &CrontabEntry, env: &BTreeMap<String, String>, args: &Args) {
+    explain(&*format!("\"{}\"", entry), entry.period(), entry.calendar(), env, args);
+}
+
+fn explain_file<T: FromStr>(path: &str, args: &Args) -> bool
+where
+    CrontabEntry: From<T>,
+    CrontabFileError: From<<T as FromStr>::Err>,
+{
+    let crontab = match CrontabFile::<T>::new(path) {
+        Ok(crontab) => crontab,
+        Err(err) => {
+            println!("error reading {}: {}", path, err);
+            return false;
+        }
+    };
+
+    let mut env = BTreeMap::new();
+    let mut ok = true;
+    for entry in crontab {
+        match entry {
+            Ok(CrontabEntry::EnvVar(EnvVarEntry(name, value))) => {
+                env.insert(name, value);
+            }
+            Ok(entry) => explain_entry(&entry, &env, args),
+            Err(err) => {
+                println!("{}: {}", path, err);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+fn explain_line<T: FromStr>(line: &str, args: &Args) -> bool
+where
+    CrontabEntry: From<T>,
+    <T as FromStr>::Err: ToString,
+{
+    match line.parse::<T>() {
+        Ok(entry) => {
+            explain_entry(&CrontabEntry::from(entry), &BTreeMap::new(), args);
+            true
+        }
+        Err(err) => {
+            println!("invalid crontab line \"{}\": {}", line, err.to_string());
+            false
+        }
+    }
+}
+
+fn explain_input(input: &str, args: &Args) -> bool {
+    if metadata(input).map(|m| m.is_file()).unwrap_or(false) {
+        return match args {
+            Args { flag_system: true, .. } => explain_file::<SystemCrontabEntry>(input, args),
+            Args { flag_anacron: true, .. } => explain_file::<AnacrontabEntry>(input, args),
+            _ => explain_file::<UserCrontabEntry>(input, args),
+        };
+    }
+
+    let input = input.trim();
+    let fields = input.split_whitespace().count();
+    let schedule_only = if input.starts_with('@') {
+        fields == 1
+    } else {
+        fields == 5 && !args.flag_anacron
+    };
+
+    if schedule_only {
+        return match input.parse::<Schedule>() {
+            Ok(Schedule::Period(ref period)) => {
+                explain(&*format!("\"{}\"", input), Some(period), None, &BTreeMap::new(), args);
+                true
+            }
+            Ok(Schedule::Calendar(ref calendar)) => {
+                explain(&*format!("\"{}\"", input), None, Some(calendar), &BTreeMap::new(), args);
+                true
+            }
+            Err(err) => {
+                println!("invalid schedule \"{}\": {}", input, err);
+                false
+            }
+        };
+    }
+
+    match args {
+        Args { flag_system: true, .. } => explain_line::<SystemCrontabEntry>(input, args),
+        Args { flag_anacron: true, .. } => explain_line::<AnacrontabEntry>(input, args),
+        _ => explain_line::<UserCrontabEntry>(input, args),
+    }
+}
+
+fn main() {
+    let mut args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+
+    // jobs with CRON_TZ change it, keep the one we were started with for the others
+    if args.flag_tz.is_none() {
+        args.flag_tz = env::var("TZ").ok();
+    }
+
+    let mut ok = true;
+    for input in &args.arg_input {
+        ok &= explain_input(input, &args);
+    }
+
+    if !ok {
+        exit(1);
+    }
+}
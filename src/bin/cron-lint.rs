@@ -0,0 +1,247 @@
+extern crate cronparse;
+extern crate docopt;
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
+
+use std::fmt::Display;
+use std::fs;
+use std::io::{stdin, Read};
+use std::path::Path;
+use std::process::exit;
+use std::str::FromStr;
+
+use cronparse::crontab::{AnacrontabEntry, CrontabEntry, SystemCrontabEntry, UserCrontabEntry};
+use docopt::Docopt;
+use systemd_crontab_generator::config;
+use systemd_crontab_generator::identity;
+use systemd_crontab_generator::lint::{entry_warnings, parse_lines, variable_warnings, Line};
+use systemd_crontab_generator::names::ignored_by;
+use systemd_crontab_generator::sysroot;
+
+static USAGE: &str = r#"
+Usage: cron-lint [--strict] [--root <dir>] [--user | --system | --anacron] <file>...
+       cron-lint -h | --help
+
+Check crontab files before they are deployed
+
+Every parse error is reported with its line number, as well as the settings
+systemd-crontab-generator ignores or handles differently from cron. Directories
+are checked file by file, and "-" reads a crontab from the standard input.
+
+The format of each file is guessed from its path: /etc/anacrontab is an
+anacrontab, /etc/crontab and the files of /etc/cron.d are system crontabs,
+with a user column, and anything else is a user crontab.
+
+With --root, or $SYSTEMD_CRON_ROOT, the files and the users are the ones of
+the image or chroot mounted there, the paths are given as the image sees them.
+
+Options:
+
+  -h, --help    Show this help message and exit.
+  --strict      Fail on warnings too.
+  --root <dir>  Check the crontabs of the system mounted in this directory.
+  --user        Files are user crontabs.
+  --system      Files are in /etc/crontab format, with a user column.
+  --anacron     Files are in /etc/anacrontab format.
+"#;
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_file: Vec<String>,
+    flag_strict: bool,
+    flag_root: Option<String>,
+    flag_user: bool,
+    flag_system: bool,
+    flag_anacron: bool,
+}
+
+// line 0 is about the whole file
+fn location(name: &str, lineno: usize) -> String {
+    if lineno == 0 {
+        name.to_owned()
+    } else {
+        format!("{}:{}", name, lineno)
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn error<D: Display>(&mut self, name: &str, lineno: usize, message: D) {
+        println!("{}: error: {}", location(name, lineno), message);
+        self.errors += 1;
+    }
+
+    fn warning<D: Display>(&mut self, name: &str, lineno: usize, message: D) {
+        println!("{}: warning: {}", location(name, lineno), message);
+        self.warnings += 1;
+    }
+
+    // warnings only fail with --strict
+    fn status(&self, strict: bool) -> i32 {
+        if self.errors > 0 || (strict && self.warnings > 0) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+// the generator leaves unknown users to be looked up when the job starts,
+// in case they come from a directory service which isn't up yet
+fn user_warnings(entry: &CrontabEntry) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(user) = entry.user() {
+        if identity::user_by_name(user).is_none() {
+            warnings.push(format!("unknown user {}, the job fails unless a directory service knows it", user));
+        }
+    }
+    if let Some(group) = entry.group() {
+        if identity::group_by_name(group).is_none() {
+            warnings.push(format!("unknown group {}, the job would fail to start", group));
+        }
+    }
+    warnings
+}
+
+fn lint_content<T: FromStr>(name: &str, content: &str, report: &mut Report)
+where
+    CrontabEntry: From<T>,
+    <T as FromStr>::Err: Display,
+{
+    for (lineno, line) in parse_lines::<T>(content) {
+        match line {
+            Ok(Line::Variable(var, value)) => {
+                for warning in variable_warnings(&*var, &*value) {
+                    report.warning(name, lineno, warning);
+                }
+            }
+            Ok(Line::Entry(entry)) => {
+                let entry = CrontabEntry::from(entry);
+                for warning in entry_warnings(&entry).into_iter().chain(user_warnings(&entry)) {
+                    report.warning(name, lineno, warning);
+                }
+            }
+            Err(err) => report.error(name, lineno, err),
+        }
+    }
+}
+
+fn lint_file(path: &Path, args: &Args, report: &mut Report) {
+    let name = path.to_string_lossy();
+    let root_path = sysroot::path(path);
+
+    if root_path.is_dir() {
+        match fs::read_dir(&root_path) {
+            Ok(dir) => {
+                let mut paths = dir
+                    .filter_map(|entry| entry.ok().map(|entry| path.join(entry.file_name())))
+                    .collect::<Vec<_>>();
+                paths.sort();
+                // the generator only filters the names of /etc/cron.d
+                let cron_d = path == Path::new("/etc/cron.d");
+                for path in paths {
+                    match path.file_name().and_then(|n| n.to_str()) {
+                        Some(name) if name.starts_with('.') => (),
+                        Some(name) if cron_d && ignored_by(name).is_some() => report.warning(
+                            &*path.to_string_lossy(),
+                            0,
+                            format!("ignored by the generator, {}", ignored_by(name).unwrap_or_default()),
+                        ),
+                        _ => lint_file(&path, args, report),
+                    }
+                }
+            }
+            Err(err) => report.error(&name, 0, format!("error reading directory: {}", err)),
+        }
+        return;
+    }
+
+    let mut content = String::new();
+    let read = if name == "-" {
+        stdin().read_to_string(&mut content)
+    } else {
+        fs::File::open(&root_path).and_then(|mut file| file.read_to_string(&mut content))
+    };
+    if let Err(err) = read {
+        report.error(&name, 0, format!("error reading file: {}", err));
+        return;
+    }
+
+    let system = path == Path::new("/etc/crontab") || path.parent() == Some(Path::new("/etc/cron.d"));
+    let anacron = path == Path::new("/etc/anacrontab");
+    match args {
+        Args { flag_system: true, .. } => lint_content::<SystemCrontabEntry>(&name, &content, report),
+        Args { flag_anacron: true, .. } => lint_content::<AnacrontabEntry>(&name, &content, report),
+        Args { flag_user: true, .. } => lint_content::<UserCrontabEntry>(&name, &content, report),
+        _ if system => lint_content::<SystemCrontabEntry>(&name, &content, report),
+        _ if anacron => lint_content::<AnacrontabEntry>(&name, &content, report),
+        _ => {
+            // spool crontabs are named after their owner
+            if path.parent() == Some(Path::new(&config::get().statedir)) {
+                let user = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                if identity::user_by_name(user).is_none() {
+                    report.warning(&name, 0, format!("unknown user {}, its jobs fail unless a directory service knows it", user));
+                }
+            }
+            lint_content::<UserCrontabEntry>(&name, &content, report)
+        }
+    }
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+    if let Some(ref root) = args.flag_root {
+        sysroot::set(root);
+    }
+
+    let mut report = Report::default();
+    for file in &args.arg_file {
+        lint_file(Path::new(file), &args, &mut report);
+    }
+
+    exit(report.status(args.flag_strict));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint_content, Report};
+    use cronparse::crontab::{AnacrontabEntry, SystemCrontabEntry, UserCrontabEntry};
+
+    fn user_report(content: &str) -> (usize, usize) {
+        let mut report = Report::default();
+        lint_content::<UserCrontabEntry>("test", content, &mut report);
+        (report.errors, report.warnings)
+    }
+
+    #[test]
+    fn classification() {
+        assert_eq!(user_report("# nothing\n\nSHELL=/bin/sh\n@daily true\n"), (0, 0));
+        // syntax errors are errors, settings the generator handles differently are warnings
+        assert_eq!(user_report("0 0 * * mon-foo true\nnonsense\n"), (2, 0));
+        assert_eq!(user_report("DELAY=soon\nMAILFROM=cron\n0 0 * * * date +%F\n"), (0, 3));
+        assert_eq!(user_report("DELAY=soon\n0 0 13 * fri true\n* * *\n"), (1, 2));
+
+        let mut report = Report::default();
+        // unknown users may come from a directory service later
+        lint_content::<SystemCrontabEntry>("test", "0 0 * * * root true\n0 0 * * * no-such-user true\n0 0 * *\n", &mut report);
+        assert_eq!((report.errors, report.warnings), (1, 1));
+        lint_content::<AnacrontabEntry>("test", "1 5 daily run-parts /etc/cron.daily\nnonsense\n", &mut report);
+        assert_eq!((report.errors, report.warnings), (2, 1));
+    }
+
+    #[test]
+    fn status() {
+        let report = |errors, warnings| Report { errors, warnings };
+        assert_eq!(report(0, 0).status(false), 0);
+        assert_eq!(report(0, 0).status(true), 0);
+        assert_eq!(report(0, 2).status(false), 0);
+        assert_eq!(report(0, 2).status(true), 1);
+        assert_eq!(report(1, 0).status(false), 1);
+        assert_eq!(report(1, 2).status(true), 1);
+    }
+}
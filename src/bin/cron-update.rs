@@ -0,0 +1,44 @@
+extern crate systemd_crontab_generator;
+
+use std::env;
+use std::process::exit;
+
+use systemd_crontab_generator::systemd::Systemd;
+
+static CRON_TARGET: &str = "cron.target";
+
+fn main() {
+    let try_restart = match env::args().nth(1) {
+        None => false,
+        Some(ref arg) if arg == "--try-restart" => true,
+        Some(_) => {
+            println!("Usage: cron-update [--try-restart]");
+            exit(1);
+        }
+    };
+
+    let mut systemd = match Systemd::connect() {
+        Ok(systemd) => systemd,
+        Err(err) => {
+            println!("<3>error connecting to systemd: {}", err);
+            exit(1);
+        }
+    };
+
+    // reload reruns the generators and returns once it is done
+    if let Err(err) = systemd.reload() {
+        println!("<3>error reloading systemd: {}", err);
+        exit(1);
+    }
+
+    let job = if try_restart {
+        systemd.try_restart_unit(CRON_TARGET, "replace")
+    } else {
+        systemd.restart_unit(CRON_TARGET, "replace")
+    };
+
+    if let Err(err) = job {
+        println!("<3>error restarting {}: {}", CRON_TARGET, err);
+        exit(1);
+    }
+}
@@ -4,34 +4,330 @@ extern crate glob;
 extern crate libc;
 extern crate nix;
 extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
 extern crate tempfile;
+extern crate time;
 extern crate users;
 
-use cronparse::crontab::UserCrontabEntry;
-use cronparse::{CrontabFile, CrontabFileError};
+use cronparse::crontab::{CrontabEntryParseError, UserCrontabEntry};
 use docopt::Docopt;
-use nix::unistd::{chown, Gid, Uid};
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::{chown, getegid, geteuid, setegid, seteuid, Gid, Uid};
+use std::collections::BTreeMap;
 use std::env;
+use std::ffi::CString;
 use std::fs;
-use std::fs::File;
-use std::io::{copy, stderr, stdin, stdout, Read, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, copy, stderr, stdin, stdout, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::process::{self, exit, Command, ExitStatus};
+use systemd_crontab_generator::access::denied_by;
+use systemd_crontab_generator::config;
+use systemd_crontab_generator::identity::{self, User};
+use systemd_crontab_generator::lint::parse_lines;
+use systemd_crontab_generator::sysroot;
 use tempfile::NamedTempFile;
-use users::User;
+use time::Timespec;
 
 include!(concat!(env!("OUT_DIR"), "/config.rs"));
 
 fn change_owner<P: AsRef<Path>>(path: P, owner: libc::uid_t, group: libc::gid_t) -> Result<(), nix::Error> {
+    // unprivileged users already own the files they create
+    if users::get_current_uid() != 0 {
+        return Ok(());
+    }
     chown(path.as_ref(), Some(Uid::from_raw(owner)), Some(Gid::from_raw(group)))
 }
 
+// temporary directory owned by the user, where the crontab is edited
+struct EditDir {
+    path: PathBuf,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    keep: bool,
+}
+
+impl EditDir {
+    // TMPDIR is up to the user on purpose, the directory is created
+    // with the privileges of the user, not the crontab group's
+    fn create(user: &User) -> io::Result<EditDir> {
+        let tmp_dir = env::temp_dir();
+        let path = as_user(user, || {
+            let mut attempt = 0;
+            loop {
+                let path = tmp_dir.join(format!("crontab.{}.{}", process::id(), attempt));
+                match DirBuilder::new().mode(0o700).create(&path) {
+                    Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                    result => return result.map(|_| path),
+                }
+            }
+        })?;
+
+        Ok(EditDir {
+            path,
+            uid: user.uid(),
+            gid: user.primary_group_id(),
+            keep: false,
+        })
+    }
+}
+
+impl Drop for EditDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let path = &self.path;
+            let _ = as_ids(self.uid, self.gid, || fs::remove_dir_all(path));
+        }
+    }
+}
+
+// held until exit, so that concurrent crontab runs for the same user
+// don't overwrite each other's changes
+fn lock_crontab(user: &User) -> Result<File, String> {
+    let path = spool_dir().join(format!(".{}.lock", user.name()));
+    let open = || OpenOptions::new().read(true).write(true).open(&path);
+    let mut file = open()
+        .or_else(|_| OpenOptions::new().read(true).write(true).create_new(true).mode(0o660).open(&path))
+        .or_else(|_| open())
+        .map_err(|err| format!("unable to open lock file {}: {}", path.display(), err))?;
+
+    // other users' crontab runs go through the crontab group
+    let group = identity::group_by_name(CRONTAB_GROUP).map(|g| g.gid()).unwrap_or(0);
+    let _ = change_owner(&path, user.uid(), group);
+
+    if flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+        let mut pid = String::new();
+        let _ = file.read_to_string(&mut pid);
+        return Err(format!("crontab of {} is being edited by PID {}", user.name(), pid.trim()));
+    }
+
+    let _ = file.set_len(0).and_then(|_| writeln!(file, "{}", process::id()));
+    Ok(file)
+}
+
+fn read_crontab(cron_file: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(cron_file) {
+        Ok(content) => Ok(Some(content)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn as_user<T, F: FnOnce() -> io::Result<T>>(user: &User, f: F) -> io::Result<T> {
+    as_ids(user.uid(), user.primary_group_id(), f)
+}
+
+// run f with the effective ids of a user, so that files are created
+// and read with the user's privileges only; exits if the privileges
+// can't be restored, rather than going on with the wrong ids
+fn as_ids<T, F: FnOnce() -> io::Result<T>>(uid: libc::uid_t, gid: libc::gid_t, f: F) -> io::Result<T> {
+    let (euid, egid) = (geteuid(), getegid());
+
+    setegid(Gid::from_raw(gid)).map_err(io::Error::other)?;
+    if let Err(err) = seteuid(Uid::from_raw(uid)) {
+        let _ = setegid(egid);
+        return Err(io::Error::other(err));
+    }
+
+    let result = f();
+
+    if let Err(err) = seteuid(euid).and_then(|_| setegid(egid)) {
+        let mut stderr = stderr();
+        writeln!(stderr, "unable to restore privileges: {}", err).unwrap();
+        exit(1);
+    }
+    result
+}
+
+fn run_editor(editor: &str, edit_file: &Path, user: &User) -> io::Result<ExitStatus> {
+    let mut command = Command::new(editor);
+    command.arg(edit_file);
+
+    if users::get_current_uid() == 0 {
+        // real ids too, or the editor could get root back
+        command.uid(user.uid()).gid(user.primary_group_id()).status()
+    } else {
+        // drop the crontab group, inherited from the setgid bit
+        as_user(user, || command.status())
+    }
+}
+
+// same as the tmpfiles.d entry, in case the spool is created by the first crontab run
+fn create_spool_dir() -> io::Result<()> {
+    let spool_dir = spool_dir();
+    fs::create_dir_all(&spool_dir)?;
+    let group = identity::group_by_name(CRONTAB_GROUP).map(|g| g.gid()).unwrap_or(0);
+    change_owner(&spool_dir, 0, group).map_err(io::Error::other)?;
+    fs::set_permissions(&spool_dir, fs::Permissions::from_mode(0o1730))
+}
+
+// earlier versions of each crontab are kept in a private directory of the user,
+// the generator ignores the dotfiles of the spool
+const HISTORY_SIZE: usize = 10;
+
+fn spool_dir() -> PathBuf {
+    sysroot::path(&config::get().statedir)
+}
+
+fn history_dir() -> PathBuf {
+    spool_dir().join(".history")
+}
+
+// version of a crontab, as it was before it was changed by `action`
+struct Version {
+    path: PathBuf,
+    time: i64,
+    action: String,
+    by: String,
+}
+
+impl Version {
+    fn parse(path: PathBuf) -> Option<Version> {
+        // <seconds>-<nanoseconds>.<action>.<user>
+        let name = path.file_name()?.to_str()?.to_owned();
+        let mut parts = name.splitn(3, '.');
+        let time = parts.next()?.split('-').next()?.parse::<i64>().ok()?;
+        let action = parts.next()?.to_owned();
+        let by = parts.next()?.to_owned();
+        Some(Version { path, time, action, by })
+    }
+}
+
+fn invoker_name() -> String {
+    let uid = users::get_current_uid();
+    identity::user_by_uid(uid)
+        .map(|user| user.name().to_owned())
+        .unwrap_or_else(|| uid.to_string())
+}
+
+// same format as vixie cron, like "(root) REPLACE (alice)"
+fn log_change(action: &str, cron_user: &User) {
+    let message = format!("({}) {} ({})", invoker_name(), action.to_uppercase(), cron_user.name());
+    if let Ok(message) = CString::new(message) {
+        unsafe {
+            libc::openlog(b"crontab\0".as_ptr() as *const libc::c_char, libc::LOG_PID, libc::LOG_CRON);
+            libc::syslog(libc::LOG_INFO, b"%s\0".as_ptr() as *const libc::c_char, message.as_ptr());
+            libc::closelog();
+        }
+    }
+}
+
+// oldest first
+fn list_versions(cron_user: &User) -> io::Result<Vec<Version>> {
+    let dir = match fs::read_dir(history_dir().join(cron_user.name())) {
+        Ok(dir) => dir,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut versions = Vec::new();
+    for entry in dir {
+        if let Some(version) = Version::parse(entry?.path()) {
+            versions.push(version);
+        }
+    }
+    versions.sort_by(|a, b| (a.time, &a.path).cmp(&(b.time, &b.path)));
+    Ok(versions)
+}
+
+// the history directory of a user is created by crontab, so that it is owned by the user,
+// don't write there if somebody else created it first
+fn user_history_dir(cron_user: &User) -> io::Result<PathBuf> {
+    let base = history_dir();
+    if fs::symlink_metadata(&base).is_err() && users::get_current_uid() == 0 {
+        fs::create_dir(&base)?;
+        let group = identity::group_by_name(CRONTAB_GROUP).map(|g| g.gid()).unwrap_or(0);
+        change_owner(&base, 0, group).map_err(io::Error::other)?;
+        fs::set_permissions(&base, fs::Permissions::from_mode(0o1730))?;
+    }
+    let meta = fs::symlink_metadata(&base).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", base.display(), err)))?;
+    if meta.uid() != 0 {
+        return Err(io::Error::other(format!("{} isn't owned by root", base.display())));
+    }
+
+    let dir = base.join(cron_user.name());
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => change_owner(&dir, cron_user.uid(), cron_user.primary_group_id()).map_err(io::Error::other)?,
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(err) => return Err(err),
+    }
+
+    let meta = fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != cron_user.uid() || meta.mode() & 0o077 != 0 {
+        return Err(io::Error::other(format!("{} isn't private to {}", dir.display(), cron_user.name())));
+    }
+    Ok(dir)
+}
+
+// keeps the installed crontab before it is replaced by `new`, or removed
+fn record_version(cron_file: &Path, cron_user: &User, action: &str, new: Option<&[u8]>) -> io::Result<()> {
+    let previous = match read_crontab(cron_file)? {
+        Some(ref previous) if Some(&**previous) == new => return Ok(()),
+        Some(previous) => previous,
+        None => return Ok(()),
+    };
+
+    let dir = user_history_dir(cron_user)?;
+    let now = time::get_time();
+    let path = dir.join(format!("{}-{:09}.{}.{}", now.sec, now.nsec, action, invoker_name()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&previous))?;
+    change_owner(&path, cron_user.uid(), cron_user.primary_group_id()).map_err(io::Error::other)?;
+
+    let versions = list_versions(cron_user)?;
+    for version in versions.iter().take(versions.len().saturating_sub(HISTORY_SIZE)) {
+        fs::remove_file(&version.path)?;
+    }
+    Ok(())
+}
+
+// the history directory can only be reached through the crontab group,
+// don't follow links in the user's directory with our privileges
+fn read_version(cron_user: &User, version: &Version) -> io::Result<Vec<u8>> {
+    let group = identity::group_by_name(CRONTAB_GROUP).map(|g| g.gid()).unwrap_or(0);
+    as_ids(cron_user.uid(), group, || fs::read(&version.path))
+}
+
+// counts of added and removed lines from one version to the next
+fn diff_summary(from: &[u8], to: &[u8]) -> (usize, usize) {
+    let mut counts = BTreeMap::new();
+    for line in from.split(|&c| c == b'\n').filter(|line| !line.is_empty()) {
+        *counts.entry(line).or_insert(0isize) -= 1;
+    }
+    for line in to.split(|&c| c == b'\n').filter(|line| !line.is_empty()) {
+        *counts.entry(line).or_insert(0isize) += 1;
+    }
+    counts.values().fold((0, 0), |(added, removed), &count| {
+        if count > 0 {
+            (added + count as usize, removed)
+        } else {
+            (added, removed + (-count) as usize)
+        }
+    })
+}
+
+// comments added above the lines with errors when an edit is retried,
+// removed before the crontab is installed
+static ANNOTATION_PREFIX: &str = "#!crontab error";
+
 static USAGE: &str = r#"
-Usage: crontab [-u <user>] -l
-       crontab [-u <user>] -e [<file>]
-       crontab [-u <user>] -s
-       crontab [-u <user>] -r [-i]
+Usage: crontab [--root <dir>] [-u <user>] [-n] <file> [-x <mask>]
+       crontab [--root <dir>] [-u <user>] -l [-x <mask>]
+       crontab [--root <dir>] [-u <user>] -e [<file>] [-x <mask>]
+       crontab [--root <dir>] [-u <user>] -s [-x <mask>]
+       crontab [--root <dir>] [-u <user>] -r [-i] [-x <mask>]
+       crontab [--root <dir>] [-u <user>] --history [-x <mask>]
+       crontab [--root <dir>] [-u <user>] --rollback [<version>] [-x <mask>]
+       crontab -T <file> [-x <mask>]
+       crontab -c [-x <mask>]
+       crontab -V
        crontab -h | --help
 
 Maintain crontab files for individual users
@@ -59,18 +355,42 @@ Options:
   -i, --ask                 This option modifies the -r option to prompt the user
                             for a 'y/Y' response before actually removing the
                             crontab.
+  --history                 List the earlier versions of the crontab, kept when
+                            it is replaced or removed, newest first.
+  --rollback                Restore an earlier version of the crontab, by its
+                            number in the --history list, the last one by
+                            default.
+  -n, --dry-run             Check the syntax of the given crontab, but don't
+                            install it.
+  -T, --test                Check the syntax of the given crontab file and exit.
+                            It doesn't need any privileges nor the spool
+                            directory.
+  --root <dir>              Use the spool directory, users and access files of
+                            the system mounted in this directory, such as an
+                            image being built. Only root can use it.
+  -V, --version             Print version and exit.
+  -c                        Cluster mode of cronie, not supported.
+  -x <mask>                 Debug mask of cronie and vixie cron, ignored.
 "#;
 
 #[derive(Debug, RustcDecodable)]
 #[allow(non_snake_case)]
 struct Args {
     arg_file: Option<String>,
+    arg_version: Option<usize>,
     flag_user: Option<String>,
+    flag_root: Option<String>,
     flag_list: bool,
     flag_remove: bool,
     flag_edit: bool,
     flag_show: bool,
     flag_ask: bool,
+    flag_history: bool,
+    flag_rollback: bool,
+    flag_dry_run: bool,
+    flag_test: bool,
+    flag_c: bool,
+    flag_x: Option<String>,
 }
 
 fn get_editor() -> Option<String> {
@@ -119,6 +439,9 @@ fn remove(cron_file: &Path, cron_user: &User, args: &Args) -> i32 {
     let mut stderr = stderr();
 
     if !args.flag_ask || confirm(&*format!("Are you sure you want to delete {} (y/n)? ", cron_file.display())) {
+        if let Err(err) = record_version(cron_file, cron_user, "remove", None) {
+            writeln!(stderr, "unable to keep the previous crontab of {}: {}", cron_user.name(), err).unwrap();
+        }
         if let Err(e) = fs::remove_file(cron_file) {
             use std::io::ErrorKind::*;
             match e.kind() {
@@ -128,22 +451,85 @@ fn remove(cron_file: &Path, cron_user: &User, args: &Args) -> i32 {
             .unwrap();
             return 1;
         }
+        log_change("remove", cron_user);
+    }
+    0
+}
+
+fn history(cron_file: &Path, cron_user: &User, _args: &Args) -> i32 {
+    let mut stderr = stderr();
+
+    let versions = match list_versions(cron_user) {
+        Ok(versions) => versions,
+        Err(err) => {
+            writeln!(stderr, "error reading the crontab history of {}: {}", cron_user.name(), err).unwrap();
+            return 1;
+        }
+    };
+    if versions.is_empty() {
+        println!("no crontab history for {}", cron_user.name());
+        return 0;
+    }
+
+    let mut next = read_crontab(cron_file).unwrap_or_default().unwrap_or_default();
+    for (number, version) in versions.iter().rev().enumerate() {
+        let content = read_version(cron_user, version).unwrap_or_default();
+        let changed = if version.action == "remove" { &[][..] } else { &*next };
+        let (added, removed) = diff_summary(&content, changed);
+        let date = time::at(Timespec::new(version.time, 0))
+            .strftime("%Y-%m-%d %H:%M:%S %z")
+            .map(|t| t.to_string())
+            .unwrap_or_default();
+        println!("{:>3}  {}  before {} by {}, +{} -{} lines", number + 1, date, version.action, version.by, added, removed);
+        next = content;
     }
     0
 }
 
+fn rollback(cron_file: &Path, cron_user: &User, args: &Args) -> i32 {
+    let mut stderr = stderr();
+    let number = args.arg_version.unwrap_or(1);
+
+    let versions = match list_versions(cron_user) {
+        Ok(versions) => versions,
+        Err(err) => {
+            writeln!(stderr, "error reading the crontab history of {}: {}", cron_user.name(), err).unwrap();
+            return 1;
+        }
+    };
+    let version = match versions.iter().rev().nth(number - 1) {
+        Some(version) => version,
+        None => {
+            writeln!(stderr, "no version {} in the crontab history of {}", number, cron_user.name()).unwrap();
+            return 1;
+        }
+    };
+
+    match read_version(cron_user, version) {
+        Ok(content) => install(&mut &*content, cron_file, cron_user, false, "rollback"),
+        Err(err) => {
+            writeln!(stderr, "error reading {}: {}", version.path.display(), err).unwrap();
+            1
+        }
+    }
+}
+
 fn show(_cron_file: &Path, _cron_user: &User, _args: &Args) -> i32 {
     let mut stderr = stderr();
 
-    if let Ok(dir) = fs::read_dir(USERS_CRONTAB_DIR) {
-        for item in dir {
-            if let Ok(entry) = item {
-                if let Some(name) = entry.file_name().to_str() {
-                    if users::get_user_by_name(name).is_some() {
-                        println!("{}", name);
-                    } else {
-                        writeln!(stderr, "WARNING: crontab found with no matching user: {}", name).unwrap();
-                    }
+    // the spool directory can't be listed by other users
+    if users::get_current_uid() != 0 {
+        writeln!(stderr, "must be privileged to use -s").unwrap();
+        return 1;
+    }
+
+    if let Ok(dir) = fs::read_dir(spool_dir()) {
+        for entry in dir.flatten() {
+            if let Some(name) = entry.file_name().to_str().filter(|name| !name.starts_with('.')) {
+                if identity::user_by_name(name).is_some() {
+                    println!("{}", name);
+                } else {
+                    writeln!(stderr, "WARNING: crontab found with no matching user: {}", name).unwrap();
                 }
             }
         }
@@ -152,8 +538,6 @@ fn show(_cron_file: &Path, _cron_user: &User, _args: &Args) -> i32 {
 }
 
 fn edit(cron_file: &Path, cron_user: &User, _args: &Args) -> i32 {
-    use std::io::ErrorKind::*;
-
     let mut stderr = stderr();
 
     let editor = match get_editor() {
@@ -164,83 +548,158 @@ fn edit(cron_file: &Path, cron_user: &User, _args: &Args) -> i32 {
         Some(editor) => editor,
     };
 
-    let mut tmpfile = match NamedTempFile::new_in(USERS_CRONTAB_DIR) {
-        Ok(tmpfile) => tmpfile,
+    let original = match read_crontab(cron_file) {
+        Ok(original) => original,
         Err(err) => {
-            writeln!(stderr, "unable to create a temporary file in {}: {}", USERS_CRONTAB_DIR, err).unwrap();
+            writeln!(stderr, "error copying crontab file {}: {}", cron_file.display(), err).unwrap();
             return 1;
         }
     };
 
-    if let Err(e) = File::open(cron_file).map(|mut file| copy(&mut file, &mut tmpfile)) {
-        match e.kind() {
-            NotFound => tmpfile.write_all("# min hour dom month dow command\n".as_bytes()).unwrap(),
+    let mut edit_dir = match EditDir::create(cron_user) {
+        Ok(edit_dir) => edit_dir,
+        Err(err) => {
+            writeln!(stderr, "unable to create a temporary directory: {}", err).unwrap();
+            return 1;
+        }
+    };
+    let edit_file = edit_dir.path.join("crontab");
+
+    let mut content = original.clone().unwrap_or_else(|| b"# min hour dom month dow command\n".to_vec());
+    let edited = loop {
+        let written = as_user(cron_user, || {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&edit_file)
+                .and_then(|mut file| file.write_all(&content))
+        });
+        if let Err(err) = written {
+            writeln!(stderr, "error writing {}: {}", edit_file.display(), err).unwrap();
+            return 1;
+        }
+
+        match run_editor(&*editor, &edit_file, cron_user) {
+            Ok(status) if status.success() => (),
             _ => {
-                writeln!(stderr, "error copying crontab file {}: {}", cron_file.display(), e).unwrap();
+                writeln!(stderr, "edit aborted, your edit is kept here: {}", edit_file.display()).unwrap();
+                edit_dir.keep = true;
                 return 1;
             }
         }
-    }
 
-    tmpfile.flush().unwrap();
-    {
-        change_owner(tmpfile.path(), cron_user.uid(), cron_user.primary_group_id()).unwrap();
-        let _guard = users::switch::switch_user_group(cron_user.uid(), cron_user.primary_group_id());
-        match Command::new(editor).arg(tmpfile.path()).status() {
-            Ok(status) if status.success() => (),
-            _ => {
-                writeln!(stderr, "edit aborted, your edit is kept here: {}", tmpfile.path().display()).unwrap();
+        // the user controls the edit directory, don't follow links there with our privileges
+        let edited = match as_user(cron_user, || fs::read(&edit_file)) {
+            Ok(edited) => strip_annotations(&*String::from_utf8_lossy(&edited)),
+            Err(err) => {
+                writeln!(stderr, "error reading {}: {}", edit_file.display(), err).unwrap();
+                edit_dir.keep = true;
                 return 1;
             }
+        };
+
+        let errors = crontab_errors(&*edited);
+        if errors.is_empty() {
+            break edited;
         }
-    }
 
-    if let Err(err) = check_crontab_syntax(tmpfile.path()) {
-        writeln!(stderr, "syntax error in new crontab file: {}", err).unwrap();
-        return 1;
-    }
+        for &(lineno, ref err) in &errors {
+            writeln!(stderr, "syntax error in new crontab file, line {}: {}", lineno, err).unwrap();
+        }
 
-    if let Err(err) = tmpfile.persist(cron_file) {
-        writeln!(
-            stderr,
-            "unexpected error: {}, your edit is kept here: {}",
-            err.error,
-            err.file.path().display()
-        )
-        .unwrap();
-        return 1;
+        if !confirm("Do you want to retry the same edit? (y/n) ") {
+            writeln!(stderr, "your edit is kept here: {}", edit_file.display()).unwrap();
+            edit_dir.keep = true;
+            return 1;
+        }
+
+        content = annotate(&*edited, &errors).into_bytes();
+    };
+
+    match read_crontab(cron_file) {
+        Ok(ref current) if *current == original => (),
+        _ => {
+            writeln!(
+                stderr,
+                "crontab of {} was changed while you were editing it, your edit is kept here: {}",
+                cron_user.name(),
+                edit_file.display()
+            )
+            .unwrap();
+            edit_dir.keep = true;
+            return 1;
+        }
     }
 
-    0
+    let status = install(&mut edited.as_bytes(), cron_file, cron_user, false, "edit");
+    if status != 0 {
+        writeln!(stderr, "your edit is kept here: {}", edit_file.display()).unwrap();
+        edit_dir.keep = true;
+    }
+    status
 }
 
 fn replace(cron_file: &Path, cron_user: &User, args: &Args) -> i32 {
+    match args.arg_file {
+        Some(ref name) if &**name == "-" => install(&mut stdin(), cron_file, cron_user, args.flag_dry_run, "replace"),
+        // with the ids of the caller, not the crontab group's
+        Some(ref name) => match as_ids(users::get_current_uid(), users::get_current_gid(), || File::open(&**name)) {
+            Ok(mut file) => install(&mut file, cron_file, cron_user, args.flag_dry_run, "replace"),
+            Err(err) => {
+                let mut stderr = stderr();
+                writeln!(stderr, "error reading {}: {}", name, err).unwrap();
+                1
+            }
+        },
+        None => unreachable!(),
+    }
+}
+
+// the spool directory is writable by the crontab group, and sticky,
+// so users can install their own crontab through this setgid binary
+fn install<R: Read>(source: &mut R, cron_file: &Path, cron_user: &User, dry_run: bool, action: &str) -> i32 {
     let mut stderr = stderr();
-    let mut tmpfile = match NamedTempFile::new_in(USERS_CRONTAB_DIR) {
+    let mut tmpfile = match NamedTempFile::new_in(spool_dir()) {
         Ok(tmpfile) => tmpfile,
         Err(err) => {
-            writeln!(stderr, "unable to create a temporary file in {}: {}", USERS_CRONTAB_DIR, err).unwrap();
+            writeln!(stderr, "unable to create a temporary file in {}: {}", spool_dir().display(), err).unwrap();
             return 1;
         }
     };
 
-    match args.arg_file {
-        Some(ref name) if &**name == "-" => {
-            let _ = copy(&mut stdin(), &mut tmpfile);
-        }
-        Some(ref name) => {
-            let _ = copy(&mut File::open(&**name).unwrap(), &mut tmpfile);
-        }
-        None => unreachable!(),
+    if let Err(err) = copy(source, &mut tmpfile) {
+        writeln!(stderr, "error reading new crontab: {}", err).unwrap();
+        return 1;
     }
 
     tmpfile.flush().unwrap();
 
-    if let Err(err) = check_crontab_syntax(tmpfile.path()) {
-        writeln!(stderr, "syntax error in new crontab file: {}", err).unwrap();
+    let content = match fs::read(tmpfile.path()) {
+        Ok(content) => content,
+        Err(err) => {
+            writeln!(stderr, "error reading {}: {}", tmpfile.path().display(), err).unwrap();
+            return 1;
+        }
+    };
+
+    let errors = crontab_errors(&*String::from_utf8_lossy(&content));
+    if !errors.is_empty() {
+        for (lineno, err) in errors {
+            writeln!(stderr, "syntax error in new crontab file, line {}: {}", lineno, err).unwrap();
+        }
         return 1;
     }
 
+    if dry_run {
+        return 0;
+    }
+
+    if let Err(err) = record_version(cron_file, cron_user, action, Some(&content)) {
+        writeln!(stderr, "unable to keep the previous crontab of {}: {}", cron_user.name(), err).unwrap();
+    }
+
     if let Err(e) = tmpfile.persist(cron_file) {
         writeln!(
             stderr,
@@ -254,45 +713,145 @@ fn replace(cron_file: &Path, cron_user: &User, args: &Args) -> i32 {
     }
 
     change_owner(cron_file, cron_user.uid(), cron_user.primary_group_id()).unwrap();
+    log_change(action, cron_user);
 
     0
 }
 
+// doesn't touch the spool, so that anybody can check a crontab before installing it
+fn test(args: &Args) -> i32 {
+    let mut stderr = stderr();
+    let name = args.arg_file.as_ref().unwrap();
+
+    let mut content = String::new();
+    let read = if &**name == "-" {
+        stdin().read_to_string(&mut content)
+    } else {
+        // with the ids of the caller, the errors quote the file
+        as_ids(users::get_current_uid(), users::get_current_gid(), || File::open(&**name))
+            .and_then(|mut file| file.read_to_string(&mut content))
+    };
+    if let Err(err) = read {
+        writeln!(stderr, "error reading {}: {}", name, err).unwrap();
+        return 1;
+    }
+
+    let errors = crontab_errors(&*content);
+    for &(lineno, ref err) in &errors {
+        writeln!(stderr, "syntax error in {}, line {}: {}", name, lineno, err).unwrap();
+    }
+
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
 fn main() {
     let mut stderr = stderr();
-    let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+    let args: Args = Docopt::new(USAGE)
+        .map(|d| d.version(Some(format!("{} {}", PACKAGE, env!("CARGO_PKG_VERSION")))))
+        .and_then(|d| d.decode())
+        .unwrap_or_else(|e| e.exit());
+
+    if args.flag_x.is_some() {
+        writeln!(stderr, "debug mask (-x) is not supported, ignored").unwrap();
+    }
+
+    if args.arg_version == Some(0) {
+        writeln!(stderr, "invalid version 0, versions are numbered from 1 as in --history").unwrap();
+        exit(1);
+    }
+
+    if args.flag_test {
+        exit(test(&args));
+    }
+
+    if args.flag_c {
+        writeln!(stderr, "cluster mode (-c) is not supported by {}", PACKAGE).unwrap();
+        exit(1);
+    }
+
+    // the crontab group must not write to another root on behalf of any user
+    if let Some(ref root) = args.flag_root {
+        if users::get_current_uid() != 0 {
+            writeln!(stderr, "must be privileged to use --root").unwrap();
+            exit(1);
+        }
+        sysroot::set(root);
+    }
 
     let cron_user = match args.flag_user {
         Some(_) if users::get_current_uid() != 0 => {
             writeln!(stderr, "must be privileged to use -u").unwrap();
             exit(1);
         }
-        Some(ref user) => match users::get_user_by_name(&**user) {
+        Some(ref user) => match identity::user_by_name(&**user) {
             Some(user) => user,
             None => {
                 writeln!(stderr, "unknown user: {}", user).unwrap();
                 exit(1);
             }
         },
-        None => users::get_user_by_uid(users::get_current_uid()).unwrap(),
+        None => identity::user_by_uid(users::get_current_uid()).unwrap(),
     };
 
-    match fs::metadata(USERS_CRONTAB_DIR) {
+    if users::get_current_uid() != 0 {
+        match denied_by(cron_user.name()) {
+            Ok(None) => (),
+            Ok(Some(_)) => {
+                writeln!(stderr, "You ({}) are not allowed to use this program (crontab)", cron_user.name()).unwrap();
+                writeln!(stderr, "See crontab(1) for more information").unwrap();
+                exit(1);
+            }
+            Err(err) => {
+                writeln!(stderr, "error checking access of {}: {}", cron_user.name(), err).unwrap();
+                exit(1);
+            }
+        }
+    }
+
+    match fs::metadata(spool_dir()) {
         Ok(ref meta) => {
             if !meta.is_dir() {
-                writeln!(stderr, "{} is not a directory!", USERS_CRONTAB_DIR).unwrap();
+                writeln!(stderr, "{} is not a directory!", spool_dir().display()).unwrap();
                 exit(1);
             }
         }
         Err(_) => {
-            if fs::create_dir_all(USERS_CRONTAB_DIR).is_err() {
-                writeln!(stderr, "{} doesn't exist!", USERS_CRONTAB_DIR).unwrap();
+            if create_spool_dir().is_err() {
+                writeln!(stderr, "{} doesn't exist!", spool_dir().display()).unwrap();
                 exit(1);
             }
         }
     }
 
-    let cron_file = PathBuf::from(USERS_CRONTAB_DIR).join(cron_user.name());
+    let cron_file = spool_dir().join(cron_user.name());
+
+    // the lock is released on exit
+    let changes_crontab = matches!(
+        args,
+        Args { flag_edit: true, .. }
+            | Args { flag_remove: true, .. }
+            | Args { flag_rollback: true, .. }
+            | Args {
+                flag_dry_run: false,
+                arg_file: Some(_),
+                ..
+            }
+    );
+    let _lock = if changes_crontab {
+        match lock_crontab(&cron_user) {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                writeln!(stderr, "{}", err).unwrap();
+                exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
     exit(match args {
         Args { flag_show: true, .. } => show(&*cron_file, &cron_user, &args),
@@ -304,13 +863,38 @@ fn main() {
         } => edit(&*cron_file, &cron_user, &args),
         Args { flag_edit: true, .. } => replace(&*cron_file, &cron_user, &args),
         Args { flag_remove: true, .. } => remove(&*cron_file, &cron_user, &args),
+        Args { flag_history: true, .. } => history(&*cron_file, &cron_user, &args),
+        Args { flag_rollback: true, .. } => rollback(&*cron_file, &cron_user, &args),
+        Args { arg_file: Some(_), .. } => replace(&*cron_file, &cron_user, &args),
         _ => unreachable!(),
     })
 }
 
-fn check_crontab_syntax<P: AsRef<Path>>(path: P) -> Result<(), CrontabFileError> {
-    match CrontabFile::<UserCrontabEntry>::new(path)?.find(Result::is_err) {
-        Some(Err(err)) => Err(err),
-        _ => Ok(()),
+// checks every line, so that all errors are reported at once
+fn crontab_errors(content: &str) -> Vec<(usize, CrontabEntryParseError)> {
+    parse_lines::<UserCrontabEntry>(content)
+        .filter_map(|(lineno, line)| line.err().map(|err| (lineno, err)))
+        .collect()
+}
+
+// marks the offending lines, for the user to retry the same edit
+fn annotate(content: &str, errors: &[(usize, CrontabEntryParseError)]) -> String {
+    let mut output = String::new();
+    for (index, line) in content.lines().enumerate() {
+        for (_, err) in errors.iter().filter(|&&(lineno, _)| lineno == index + 1) {
+            output.push_str(&*format!("{} line {}: {}\n", ANNOTATION_PREFIX, index + 1, err));
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+fn strip_annotations(content: &str) -> String {
+    let mut output = String::new();
+    for line in content.lines().filter(|line| !line.starts_with(ANNOTATION_PREFIX)) {
+        output.push_str(line);
+        output.push('\n');
     }
+    output
 }
@@ -1,6 +1,45 @@
-use std::env;
-use std::io::{Result, Write};
-use std::process::{Command, Stdio};
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufRead, BufReader, Read, Result, Write};
+use std::net::{IpAddr, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+use systemd_crontab_generator::config;
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+// user editable mail template, relative to the confdir of the configuration
+static MAIL_TEMPLATE_FILE: &str = "systemd-cron/mail-on-failure.template";
+
+// SMTP relay settings, relative to the confdir of the configuration
+static SMTP_CONFIG_FILE: &str = "systemd-cron/smtp.conf";
+
+static SENDMAIL: &str = "/usr/sbin/sendmail";
+
+static DEFAULT_MAIL_TEMPLATE: &str = r###"From: root (systemd-cron)
+Subject: [${host}] job ${unit} failed
+
+Job:         ${unit}
+User:        ${user}
+Host:        ${host}
+Crontab:     ${crontab}
+Command:     ${command}
+Result:      ${result}
+Exit status: ${status}
+Started:     ${started}
+Finished:    ${finished}
+Duration:    ${duration}
+
+${output}
+"###;
 
 macro_rules! try_log {
     ($exp:expr) => {
@@ -14,86 +53,599 @@ macro_rules! try_log {
     };
 }
 
-fn get_systemd_unit_property(unit: &str, prop: &str) -> Result<String> {
-    Command::new("systemctl")
-        .arg("show")
-        .arg(unit)
-        .arg("--property")
-        .arg(prop)
-        .output()
-        .map(|out| {
-            String::from_utf8_lossy(&out.stdout[prop.len() + 1..])
-                .trim_end_matches('\n')
-                .to_owned()
-        })
+fn load_template() -> String {
+    let path = Path::new(&config::get().confdir).join(MAIL_TEMPLATE_FILE);
+    let mut buf = String::new();
+    match File::open(&path).and_then(|mut file| file.read_to_string(&mut buf)) {
+        Ok(_) => buf,
+        Err(_) => DEFAULT_MAIL_TEMPLATE.to_owned(),
+    }
 }
 
-fn main() {
-    let unit = match env::args().nth(1) {
-        Some(unit) => unit,
-        None => {
-            println!("Usage: mail-on-failure <unit>");
-            return;
+// replace every `${name}` placeholder with the variable value, unknown names expand to nothing
+fn render(template: &str, vars: &BTreeMap<&str, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let name = rest[start + 2..start + end].trim();
+                if let Some(value) = vars.get(name) {
+                    output.push_str(value);
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                rest = &rest[start..];
+                break;
+            }
         }
-    };
+    }
+    output.push_str(rest);
+    output
+}
 
-    let mut user = try_log!(get_systemd_unit_property(&*unit, "User"));
-    if user.is_empty() {
-        user = "root".to_owned();
+// RFC 2047 encoded-word for non-ASCII header values
+fn encode_header(value: &str) -> String {
+    if value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return value.to_owned();
     }
 
-    let job_env = try_log!(get_systemd_unit_property(&*unit, "Environment"));
-    for pair in job_env.split(' ') {
-        let mut p = pair.splitn(2, '=');
-        if let (Some(name), Some(value)) = (p.next(), p.next()) {
-            if name == "MAILTO" {
-                user = value.to_owned();
-                break;
+    // keep encoded words under 75 chars, never splitting a character
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in value.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", chunk.as_bytes().to_base64(STANDARD)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", chunk.as_bytes().to_base64(STANDARD)));
+    }
+    words.join("\n ")
+}
+
+// RFC 2045 quoted-printable body, so that arbitrary job output is 7bit safe
+fn encode_quoted_printable(body: &str) -> String {
+    let mut output = String::with_capacity(body.len());
+    for line in body.lines() {
+        let mut width = 0;
+        let bytes = line.as_bytes();
+        for (i, &b) in bytes.iter().enumerate() {
+            let trailing_space = (b == b' ' || b == b'\t') && i + 1 == bytes.len();
+            let encoded = if (b == b'\t' || (0x20..0x7f).contains(&b)) && b != b'=' && !trailing_space {
+                (b as char).to_string()
+            } else {
+                format!("={:02X}", b)
+            };
+            if width + encoded.len() > 75 {
+                output.push_str("=\n");
+                width = 0;
             }
+            width += encoded.len();
+            output.push_str(&*encoded);
         }
+        output.push('\n');
     }
+    output
+}
+
+fn main() {
+    // the job description is passed by notify-on-failure as JSON on stdin
+    let payload = match Json::from_reader(&mut stdin()) {
+        Ok(Json::Object(payload)) => payload,
+        Ok(_) => {
+            println!("<3>unexpected notification payload");
+            return;
+        }
+        Err(err) => {
+            println!("<3>error reading notification payload: {}", err);
+            return;
+        }
+    };
+
+    let field = |name: &str| match payload.get(name) {
+        Some(Json::String(value)) => value.clone(),
+        Some(Json::Null) | None => String::new(),
+        Some(value) => value.to_string(),
+    };
+
+    let unit = field("unit");
+    let job_user = field("user");
+    let hostname = field("host");
+    let exit_status = field("exit_status");
+    let crontab_line = field("command");
+
+    let user = match payload
+        .get("environment")
+        .and_then(|env| env.find("MAILTO"))
+        .and_then(Json::as_string)
+    {
+        Some(mailto) => mailto.to_owned(),
+        None => job_user.clone(),
+    };
 
     if user.is_empty() {
         return;
     }
 
-    let mut hostname = String::from_utf8_lossy(&try_log!(Command::new("uname").arg("-n").output()).stdout[..])
-        .trim_end_matches('\n')
-        .to_owned();
+    let mut vars = BTreeMap::new();
+    vars.insert("unit", unit.clone());
+    vars.insert("user", job_user.clone());
+    vars.insert("host", hostname.clone());
+    vars.insert("mailto", user.clone());
+    vars.insert("crontab", field("crontab"));
+    vars.insert("command", crontab_line.clone());
+    vars.insert("result", field("result"));
+    vars.insert("status", exit_status.clone());
+    vars.insert("started", field("started"));
+    vars.insert("finished", field("finished"));
+    vars.insert("duration", field("duration"));
+    vars.insert("invocation", field("invocation_id"));
+    vars.insert("output", field("output"));
 
-    if hostname.is_empty() {
-        hostname = "localhost".to_owned();
-    }
+    // the template is a mail message itself: headers, empty line, body
+    let message = render(&*load_template(), &vars);
+    let (template_head, body) = match message.find("\n\n") {
+        Some(pos) => (&message[..pos], &message[pos + 2..]),
+        None => ("", &*message),
+    };
 
+    let now = time::now();
     let mut head = String::new();
-    head.push_str("From: root (systemd-cron)\nTo: ");
-    head.push_str(&*user);
-    head.push_str("\nSubject: [");
-    head.push_str(&*hostname);
-    head.push_str("] job ");
-    head.push_str(&*unit);
+    let mut has_from = false;
+    for line in template_head.lines() {
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.eq_ignore_ascii_case("To") {
+                continue;
+            }
+            has_from = has_from || name.eq_ignore_ascii_case("From");
+            head.push_str(&format!("{}: {}\n", name, encode_header(value.trim())));
+        }
+    }
+    if !has_from {
+        head.push_str("From: root (systemd-cron)\n");
+    }
+    head.push_str(&format!("To: {}\n", user));
+    head.push_str(&format!("Date: {}\n", now.rfc822z()));
+    head.push_str(&format!("Message-ID: <{}.{}.{}@{}>\n", now.to_timespec().sec, process::id(), unit, hostname));
+    head.push_str(&format!("X-Cron-Job: {}\n", encode_header(&*crontab_line)));
+    head.push_str(&format!("X-Cron-Unit: {}\n", unit));
+    head.push_str(&format!("X-Cron-User: {}\n", job_user));
+    head.push_str(&format!("X-Cron-Host: {}\n", hostname));
+    head.push_str(&format!("X-Cron-Exit-Status: {}\n", exit_status));
     head.push_str(
-        r###" failed
-MIME-Version: 1.0
+        r###"MIME-Version: 1.0
 Content-Type: text/plain; charset=UTF-8
-Content-Transfer-Encoding: 8bit
+Content-Transfer-Encoding: quoted-printable
 Auto-Submitted: auto-generated
 
 "###,
     );
 
-    let status = Command::new("systemctl").arg("status").arg(&*unit).output().unwrap();
+    head.push_str(&*encode_quoted_printable(body));
+    let message = head;
+
+    if let Some(config) = load_smtp_config() {
+        match send_smtp(&config, &*user, &*hostname, &*message) {
+            Ok(_) => return,
+            Err(err) => {
+                println!("<3>error sending mail via {}:{}: {}", config.host, config.port, err);
+                if !is_executable(SENDMAIL) {
+                    return;
+                }
+                println!("<4>falling back to {}", SENDMAIL);
+            }
+        }
+    }
+
+    if !is_executable(SENDMAIL) {
+        println!("<4>no SMTP relay configured and {} is missing, not sending mail", SENDMAIL);
+        return;
+    }
+
+    try_log!(send_sendmail(&*user, &*message));
+}
 
-    let mut mailer = try_log!(Command::new("sendmail")
+fn send_sendmail(user: &str, message: &str) -> Result<()> {
+    let mut mailer = Command::new(SENDMAIL)
         .arg("-i")
         .arg("-B8BITMIME")
-        .arg(&*user)
+        .arg(user)
         .stdin(Stdio::piped())
-        .spawn());
+        .spawn()?;
 
     if let Some(ref mut stdin) = mailer.stdin {
-        try_log!(stdin.write_all(head.as_bytes()).and_then(|_| stdin.write_all(&*status.stdout)));
+        stdin.write_all(message.as_bytes())?;
+    }
+
+    mailer.wait().map(|_| ())
+}
+
+fn is_executable(path: &str) -> bool {
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o0111 != 0)
+        .unwrap_or(false)
+}
+
+#[derive(Debug, PartialEq)]
+enum SmtpTls {
+    None,
+    StartTls,
+    Implicit,
+}
+
+#[derive(Debug)]
+struct SmtpConfig {
+    host: String,
+    port: u16,
+    tls: SmtpTls,
+    user: Option<String>,
+    password: Option<String>,
+    from: Option<String>,
+    domain: Option<String>,
+}
+
+// `key = value` lines, `#` starts a comment
+fn load_smtp_config() -> Option<SmtpConfig> {
+    let path = Path::new(&config::get().confdir).join(SMTP_CONFIG_FILE);
+    let mut buf = String::new();
+    if File::open(&path).and_then(|mut file| file.read_to_string(&mut buf)).is_err() {
+        return None;
+    }
+
+    let mut config = SmtpConfig {
+        host: "localhost".to_owned(),
+        port: 25,
+        tls: SmtpTls::None,
+        user: None,
+        password: None,
+        from: None,
+        domain: None,
+    };
+    let mut port = None;
+
+    for line in buf.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut pair = line.splitn(2, '=');
+        let (key, value) = match (pair.next(), pair.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim().to_owned()),
+            _ => {
+                println!("<4>ignoring invalid line in {}: {}", path.display(), line);
+                continue;
+            }
+        };
+
+        match key {
+            "host" => config.host = value,
+            "port" => port = value.parse().ok(),
+            // a typo must not send the password in cleartext
+            "tls" => {
+                config.tls = match &*value.to_lowercase() {
+                    "no" | "false" | "none" => SmtpTls::None,
+                    "starttls" => SmtpTls::StartTls,
+                    "yes" | "true" | "implicit" => SmtpTls::Implicit,
+                    _ => {
+                        println!("<3>invalid tls value in {}: {}, not using the SMTP relay", path.display(), value);
+                        return None;
+                    }
+                }
+            }
+            "user" => config.user = Some(value),
+            "password" => config.password = Some(value),
+            "from" => config.from = Some(value),
+            "domain" => config.domain = Some(value),
+            _ => println!("<4>ignoring unknown option in {}: {}", path.display(), key),
+        }
+    }
+
+    config.port = port.unwrap_or(match config.tls {
+        SmtpTls::None => 25,
+        SmtpTls::StartTls => 587,
+        SmtpTls::Implicit => 465,
+    });
+
+    Some(config)
+}
+
+fn smtp_error(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
+
+// read a (possibly multiline) reply and check its code
+fn smtp_reply<R: BufRead>(reader: &mut R, expected: &[u32]) -> Result<String> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(smtp_error("connection closed by server".to_owned()));
+        }
+        reply.push_str(&*line);
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            break;
+        }
     }
 
-    mailer.wait().unwrap();
+    match reply.get(..3).and_then(|code| code.parse::<u32>().ok()) {
+        Some(code) if expected.contains(&code) => Ok(reply),
+        _ => Err(smtp_error(format!("unexpected reply: {}", reply.trim_end()))),
+    }
+}
+
+fn smtp_command<R: BufRead, W: Write>(reader: &mut R, writer: &mut W, command: &str, expected: &[u32]) -> Result<String> {
+    writer.write_all(command.as_bytes())?;
+    writer.write_all(b"\r\n")?;
+    writer.flush()?;
+    smtp_reply(reader, expected)
+}
+
+fn send_smtp(config: &SmtpConfig, mailto: &str, hostname: &str, message: &str) -> Result<()> {
+    if config.tls == SmtpTls::None {
+        let stream = TcpStream::connect((&*config.host, config.port))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        smtp_reply(&mut reader, &[220])?;
+        return smtp_session(config, &mut reader, &mut writer, mailto, hostname, message);
+    }
+
+    let mut client = tls_client(config)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let result = {
+        let mut reader = BufReader::new(client.stdout.take().unwrap());
+        let mut writer = client.stdin.take().unwrap();
+        let greeting = if config.tls == SmtpTls::Implicit {
+            smtp_reply(&mut reader, &[220]).map(|_| ())
+        } else {
+            Ok(())
+        };
+        greeting.and_then(|_| smtp_session(config, &mut reader, &mut writer, mailto, hostname, message))
+    };
+    let _ = client.kill();
+    let _ = client.wait();
+    result
+}
+
+// TLS is delegated to openssl(1), in STARTTLS mode it also consumes the greeting,
+// the connection fails unless the certificate of the relay is valid for its name
+fn tls_client(config: &SmtpConfig) -> Command {
+    let address = format!("{}:{}", config.host, config.port);
+    let mut command = Command::new("openssl");
+    command.arg("s_client").arg("-quiet").arg("-verify_return_error");
+    if config.host.parse::<IpAddr>().is_ok() {
+        command.arg("-verify_ip").arg(&*config.host);
+    } else {
+        command
+            .arg("-verify_hostname")
+            .arg(&*config.host)
+            .arg("-servername")
+            .arg(&*config.host);
+    }
+    command.arg("-connect").arg(&*address);
+    if config.tls == SmtpTls::StartTls {
+        command.arg("-starttls").arg("smtp");
+    }
+    command
+}
+
+fn smtp_session<R: BufRead, W: Write>(
+    config: &SmtpConfig,
+    reader: &mut R,
+    writer: &mut W,
+    mailto: &str,
+    hostname: &str,
+    message: &str,
+) -> Result<()> {
+    let domain = config.domain.as_deref().unwrap_or(hostname);
+
+    smtp_command(reader, writer, &*format!("EHLO {}", hostname), &[250])?;
+
+    if let Some(ref user) = config.user {
+        if config.tls == SmtpTls::None {
+            return Err(smtp_error("refusing to send the password without TLS".to_owned()));
+        }
+        let password = config.password.as_deref().unwrap_or("");
+        let token = format!("\0{}\0{}", user, password).as_bytes().to_base64(STANDARD);
+        smtp_command(reader, writer, &*format!("AUTH PLAIN {}", token), &[235])?;
+    }
+
+    let from = config.from.clone().unwrap_or_else(|| format!("root@{}", domain));
+    smtp_command(reader, writer, &*format!("MAIL FROM:<{}>", from), &[250])?;
+
+    // MAILTO may list several recipients, bare user names are qualified with the domain
+    for rcpt in mailto.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let rcpt = if rcpt.contains('@') {
+            rcpt.to_owned()
+        } else {
+            format!("{}@{}", rcpt, domain)
+        };
+        // 251: user not local, will forward
+        smtp_command(reader, writer, &*format!("RCPT TO:<{}>", rcpt), &[250, 251])?;
+    }
+
+    smtp_command(reader, writer, "DATA", &[354])?;
+    for line in message.lines() {
+        if line.starts_with('.') {
+            writer.write_all(b".")?;
+        }
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\r\n")?;
+    }
+    smtp_command(reader, writer, ".", &[250])?;
+
+    smtp_command(reader, writer, "QUIT", &[221]).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_header, encode_quoted_printable, render, smtp_session, tls_client, SmtpConfig, SmtpTls};
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    fn config(tls: SmtpTls, user: Option<&str>) -> SmtpConfig {
+        SmtpConfig {
+            host: "relay.example.com".to_owned(),
+            port: 25,
+            tls,
+            user: user.map(str::to_owned),
+            password: user.map(|_| "secret".to_owned()),
+            from: None,
+            domain: Some("example.com".to_owned()),
+        }
+    }
+
+    fn session(config: &SmtpConfig, replies: &str, mailto: &str, message: &str) -> (Result<(), String>, String) {
+        let mut reader = Cursor::new(replies.as_bytes().to_vec());
+        let mut writer = Vec::new();
+        let result = smtp_session(config, &mut reader, &mut writer, mailto, "host", message).map_err(|err| err.to_string());
+        (result, String::from_utf8(writer).unwrap())
+    }
+
+    #[test]
+    fn sends_message() {
+        let replies = "250-relay.example.com\r\n250 8BITMIME\r\n250 ok\r\n250 ok\r\n251 forwarded\r\n354 go\r\n250 queued\r\n221 bye\r\n";
+        let (result, sent) = session(&config(SmtpTls::None, None), replies, "root, admin@other.org", "Subject: x\n\n.hidden\nend\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            sent,
+            "EHLO host\r\n\
+             MAIL FROM:<root@example.com>\r\n\
+             RCPT TO:<root@example.com>\r\n\
+             RCPT TO:<admin@other.org>\r\n\
+             DATA\r\n\
+             Subject: x\r\n\r\n..hidden\r\nend\r\n.\r\n\
+             QUIT\r\n"
+        );
+    }
+
+    #[test]
+    fn authenticates_over_tls() {
+        let replies = "250 ok\r\n235 authenticated\r\n250 ok\r\n250 ok\r\n354 go\r\n250 queued\r\n221 bye\r\n";
+        let (result, sent) = session(&config(SmtpTls::StartTls, Some("cron")), replies, "root", "x\n");
+        assert_eq!(result, Ok(()));
+        assert!(sent.contains("AUTH PLAIN AGNyb24Ac2VjcmV0\r\n"));
+    }
+
+    #[test]
+    fn verifies_certificates() {
+        let args = |config: &SmtpConfig| {
+            tls_client(config)
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args(&config(SmtpTls::StartTls, None)),
+            vec![
+                "s_client",
+                "-quiet",
+                "-verify_return_error",
+                "-verify_hostname",
+                "relay.example.com",
+                "-servername",
+                "relay.example.com",
+                "-connect",
+                "relay.example.com:25",
+                "-starttls",
+                "smtp"
+            ]
+        );
+
+        // no SNI nor host name for an address
+        let relay = SmtpConfig {
+            host: "192.0.2.1".to_owned(),
+            port: 465,
+            ..config(SmtpTls::Implicit, None)
+        };
+        assert_eq!(
+            args(&relay),
+            vec![
+                "s_client",
+                "-quiet",
+                "-verify_return_error",
+                "-verify_ip",
+                "192.0.2.1",
+                "-connect",
+                "192.0.2.1:465"
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_auth_without_tls() {
+        let (result, sent) = session(&config(SmtpTls::None, Some("cron")), "250 ok\r\n", "root", "x\n");
+        assert!(result.is_err());
+        assert!(!sent.contains("AUTH"));
+    }
+
+    #[test]
+    fn fails_on_rejected_recipient() {
+        let replies = "250 ok\r\n250 ok\r\n550 no such user\r\n";
+        let (result, sent) = session(&config(SmtpTls::None, None), replies, "nobody", "x\n");
+        assert_eq!(result, Err("unexpected reply: 550 no such user".to_owned()));
+        assert!(!sent.contains("DATA"));
+    }
+
+    #[test]
+    fn template_variables() {
+        let mut vars = BTreeMap::new();
+        vars.insert("unit", "cron-root-0.service".to_owned());
+        vars.insert("user", "${unit}".to_owned());
+
+        assert_eq!(render("job ${unit} of ${ user }", &vars), "job cron-root-0.service of ${unit}");
+        assert_eq!(render("[${missing}] $unit {unit} $${unit}", &vars), "[] $unit {unit} $cron-root-0.service");
+        assert_eq!(render("unterminated ${unit", &vars), "unterminated ${unit");
+        assert_eq!(render("", &vars), "");
+    }
+
+    #[test]
+    fn encoded_words() {
+        assert_eq!(encode_header("[host] job cron-root-0.service failed"), "[host] job cron-root-0.service failed");
+        assert_eq!(encode_header("T\u{e2}che \u{e9}chou\u{e9}e"), "=?UTF-8?B?VMOiY2hlIMOpY2hvdcOpZQ==?=");
+
+        // long values are split in several words, each under 75 characters
+        let long = "\u{e9}".repeat(30);
+        assert_eq!(
+            encode_header(&long),
+            "=?UTF-8?B?w6nDqcOpw6nDqcOpw6nDqcOpw6nDqcOpw6nDqcOpw6nDqcOpw6nDqcOpw6k=?=\n =?UTF-8?B?w6nDqcOpw6nDqcOpw6nDqQ==?="
+        );
+        assert!(encode_header(&long).lines().all(|word| word.trim().len() <= 75));
+
+        // and never in the middle of a character, € is 3 bytes long
+        assert_eq!(
+            encode_header(&format!("a{}", "\u{20ac}".repeat(15))),
+            "=?UTF-8?B?YeKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrOKCrA==?=\n =?UTF-8?B?4oKs?="
+        );
+    }
+
+    #[test]
+    fn quoted_printable() {
+        assert_eq!(encode_quoted_printable("plain\ttext\n"), "plain\ttext\n");
+        assert_eq!(encode_quoted_printable("a=b\n"), "a=3Db\n");
+        assert_eq!(encode_quoted_printable("caf\u{e9}\n"), "caf=C3=A9\n");
+        // whitespace at the end of lines would be lost in transit
+        assert_eq!(encode_quoted_printable("space \ntab\t\n in \t\n"), "space=20\ntab=09\n in =09\n");
+        assert_eq!(encode_quoted_printable("crlf\r\nline\r\n"), "crlf\nline\n");
+    }
+
+    #[test]
+    fn soft_line_breaks() {
+        let long = "a".repeat(80);
+        assert_eq!(encode_quoted_printable(&long), format!("{}=\n{}\n", "a".repeat(75), "a".repeat(5)));
+
+        // an escape is never split
+        let line = format!("{}=", "a".repeat(74));
+        assert_eq!(encode_quoted_printable(&line), format!("{}=\n=3D\n", "a".repeat(74)));
+        assert_eq!(encode_quoted_printable(&"a".repeat(75)), format!("{}\n", "a".repeat(75)));
+    }
 }
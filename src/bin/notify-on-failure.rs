@@ -0,0 +1,365 @@
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
+extern crate time;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{Result, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use rustc_serialize::json::{Json, ToJson};
+use systemd_crontab_generator::config;
+use systemd_crontab_generator::dbus::Value;
+use systemd_crontab_generator::systemd::{Systemd, SERVICE_INTERFACE, UNIT_INTERFACE};
+use time::Timespec;
+
+#[cfg(test)]
+#[path = "../testing.rs"]
+mod testing;
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+// default limit for the job output passed to notifiers, in bytes
+const DEFAULT_OUTPUT_LIMIT: usize = 64 * 1024;
+
+// notifiers shipped with the package, relative to the configured libdir/PACKAGE
+static PACKAGE_NOTIFIERS_DIR: &str = "notifiers";
+
+// notifiers added by the administrator, relative to the configured confdir,
+// an executable with the same name overrides the packaged one
+static ADMIN_NOTIFIERS_DIR: &str = "systemd-cron/notifiers";
+
+// used when the job doesn't set NOTIFY
+static DEFAULT_NOTIFIERS: &str = "mail";
+
+// crontab variables passed to notifiers, the others may hold secrets
+static NOTIFIER_VARIABLES: &[&str] = &["MAILTO", "NOTIFY"];
+static NOTIFIER_VARIABLES_PREFIX: &str = "NOTIFY_";
+
+macro_rules! try_log {
+    ($exp:expr) => {
+        match $exp {
+            Ok(v) => v,
+            Err(e) => {
+                println!("<3>{}", e);
+                return;
+            }
+        }
+    };
+}
+
+fn get_string_property(systemd: &mut Systemd, unit: &str, interface: &str, prop: &str) -> Result<String> {
+    systemd
+        .unit_property(unit, interface, prop)
+        .map(|value| value.as_str().unwrap_or("").to_owned())
+}
+
+fn get_usec_property(systemd: &mut Systemd, unit: &str, prop: &str) -> Result<Option<u64>> {
+    systemd
+        .unit_property(unit, SERVICE_INTERFACE, prop)
+        .map(|value| value.as_u64().filter(|&usec| usec > 0))
+}
+
+fn format_timestamp(usec: Option<u64>) -> String {
+    match usec {
+        Some(usec) => {
+            let tm = time::at(Timespec::new((usec / 1_000_000) as i64, 0));
+            tm.strftime("%a %Y-%m-%d %H:%M:%S %z").map(|t| t.to_string()).unwrap_or_default()
+        }
+        None => "n/a".to_owned(),
+    }
+}
+
+// every journal line of the run, not only the ones written to stdout and stderr
+fn get_invocation_output(invocation_id: &str) -> Result<String> {
+    Command::new("journalctl")
+        .arg("--no-pager")
+        .arg("--quiet")
+        .arg("--output=cat")
+        .arg(format!("_SYSTEMD_INVOCATION_ID={}", invocation_id))
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// keep the tail of the output, as the reason of failure is usually at the end
+fn truncate_output(output: &str, limit: usize) -> String {
+    if limit == 0 || output.len() <= limit {
+        return output.to_owned();
+    }
+
+    let mut start = output.len() - limit;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    // from the next line, unless it is the end of the output
+    let start = output[start..]
+        .find('\n')
+        .map(|p| start + p + 1)
+        .filter(|&next| next < output.len())
+        .unwrap_or(start);
+
+    format!("[... {} bytes of output skipped ...]\n{}", start, &output[start..])
+}
+
+fn format_duration(usec: u64) -> String {
+    let secs = usec / 1_000_000;
+    match secs {
+        0..=59 => format!("{}.{:03}s", secs, usec % 1_000_000 / 1000),
+        60..=3599 => format!("{}min {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}min {}s", secs / 3600, secs % 3600 / 60, secs % 60),
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o0111 != 0)
+        .unwrap_or(false)
+}
+
+// the administrator's directory first, so that its notifiers override the packaged ones
+fn notifier_dirs() -> Vec<PathBuf> {
+    vec![
+        Path::new(&config::get().confdir).join(ADMIN_NOTIFIERS_DIR),
+        Path::new(&config::get().libdir).join(PACKAGE).join(PACKAGE_NOTIFIERS_DIR),
+    ]
+}
+
+fn find_notifier(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    // names come from crontabs, don't let them escape the notifiers directories
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return None;
+    }
+
+    dirs.iter().map(|dir| dir.join(name)).find(|path| is_executable(path))
+}
+
+// only the variables meant for notifiers, the rest of the crontab environment may hold secrets
+fn notifier_environment(environment: BTreeMap<String, String>) -> BTreeMap<String, String> {
+    environment
+        .into_iter()
+        .filter(|(name, _)| NOTIFIER_VARIABLES.contains(&&**name) || name.starts_with(NOTIFIER_VARIABLES_PREFIX))
+        .collect()
+}
+
+fn run_notifier(notifier: &Path, unit: &str, payload: &str) -> Result<bool> {
+    let mut child = Command::new(notifier).arg(unit).stdin(Stdio::piped()).spawn()?;
+
+    if let Some(ref mut stdin) = child.stdin {
+        stdin.write_all(payload.as_bytes())?;
+    }
+    // close stdin, so that the notifier sees the end of payload
+    drop(child.stdin.take());
+
+    child.wait().map(|status| status.success())
+}
+
+fn main() {
+    let unit = match env::args().nth(1) {
+        Some(unit) => unit,
+        None => {
+            println!("Usage: notify-on-failure <unit>");
+            return;
+        }
+    };
+
+    let mut systemd = try_log!(Systemd::connect());
+
+    let mut user = try_log!(get_string_property(&mut systemd, &*unit, SERVICE_INTERFACE, "User"));
+    if user.is_empty() {
+        user = "root".to_owned();
+    }
+
+    let mut environment = BTreeMap::new();
+    let job_env = try_log!(systemd.unit_property(&*unit, SERVICE_INTERFACE, "Environment"));
+    for pair in job_env.as_array().unwrap_or(&[]).iter().filter_map(Value::as_str) {
+        let mut p = pair.splitn(2, '=');
+        if let (Some(name), Some(value)) = (p.next(), p.next()) {
+            environment.insert(name.to_owned(), value.to_owned());
+        }
+    }
+
+    let output_limit = environment
+        .get("MAIL_OUTPUT_LIMIT")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_OUTPUT_LIMIT);
+    let notifiers = environment.get("NOTIFY").map(|v| &**v).unwrap_or(DEFAULT_NOTIFIERS).to_owned();
+
+    let mut hostname = String::from_utf8_lossy(&try_log!(Command::new("uname").arg("-n").output()).stdout[..])
+        .trim_end_matches('\n')
+        .to_owned();
+
+    if hostname.is_empty() {
+        hostname = "localhost".to_owned();
+    }
+
+    let description = try_log!(get_string_property(&mut systemd, &*unit, UNIT_INTERFACE, "Description"));
+    let source_path = try_log!(get_string_property(&mut systemd, &*unit, UNIT_INTERFACE, "SourcePath"));
+    let result = try_log!(get_string_property(&mut systemd, &*unit, SERVICE_INTERFACE, "Result"));
+    let exit_status = try_log!(systemd.unit_property(&*unit, SERVICE_INTERFACE, "ExecMainStatus"));
+    let started = try_log!(get_usec_property(&mut systemd, &*unit, "ExecMainStartTimestamp"));
+    let finished = try_log!(get_usec_property(&mut systemd, &*unit, "ExecMainExitTimestamp"));
+    let started_usec = try_log!(get_usec_property(&mut systemd, &*unit, "ExecMainStartTimestampMonotonic"));
+    let finished_usec = try_log!(get_usec_property(&mut systemd, &*unit, "ExecMainExitTimestampMonotonic"));
+
+    // systemd < 232 doesn't know InvocationID, the ID is then left empty
+    let invocation_id = systemd
+        .unit_property(&*unit, UNIT_INTERFACE, "InvocationID")
+        .ok()
+        .and_then(|id| id.as_bytes())
+        .unwrap_or_default()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    // generated units are described as `[Cron] "<crontab line>"`
+    let crontab_line = description
+        .trim_start_matches("[Cron] ")
+        .trim_start_matches('"')
+        .trim_end_matches('"');
+
+    let duration_usec = match (started_usec, finished_usec) {
+        (Some(start), Some(end)) if end >= start => Some(end - start),
+        _ => None,
+    };
+
+    // InvocationID is only known to systemd >= 232, fall back to status excerpt otherwise
+    let output = if invocation_id.is_empty() {
+        // without systemctl the mail is still worth sending, with an empty excerpt
+        Command::new("systemctl")
+            .arg("status")
+            .arg(&*unit)
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default()
+    } else {
+        try_log!(get_invocation_output(&*invocation_id))
+    };
+    let output = truncate_output(&*output, output_limit);
+
+    let mut payload = BTreeMap::new();
+    payload.insert("unit".to_owned(), unit.to_json());
+    payload.insert("user".to_owned(), user.to_json());
+    payload.insert("host".to_owned(), hostname.to_json());
+    payload.insert("crontab".to_owned(), source_path.to_json());
+    payload.insert("command".to_owned(), crontab_line.to_json());
+    payload.insert("result".to_owned(), result.to_json());
+    payload.insert("exit_status".to_owned(), exit_status.as_i64().map(Json::I64).unwrap_or(Json::Null));
+    payload.insert("started".to_owned(), format_timestamp(started).to_json());
+    payload.insert("finished".to_owned(), format_timestamp(finished).to_json());
+    payload.insert("duration_usec".to_owned(), duration_usec.to_json());
+    payload.insert("duration".to_owned(), duration_usec.map(format_duration).unwrap_or_else(|| "n/a".to_owned()).to_json());
+    payload.insert("invocation_id".to_owned(), invocation_id.to_json());
+    payload.insert("output".to_owned(), output.to_json());
+    payload.insert("environment".to_owned(), notifier_environment(environment).to_json());
+    let payload = Json::Object(payload).to_string();
+
+    let dirs = notifier_dirs();
+    for name in notifiers.split(&[',', ' '][..]).filter(|n| !n.is_empty()) {
+        match find_notifier(name, &dirs) {
+            None => println!("<3>unknown notifier {} for {}", name, unit),
+            Some(notifier) => match run_notifier(&*notifier, &*unit, &*payload) {
+                Ok(true) => (),
+                Ok(false) => println!("<3>notifier {} failed for {}", notifier.display(), unit),
+                Err(err) => println!("<3>error running notifier {}: {}", notifier.display(), err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_notifier, format_duration, notifier_environment, truncate_output};
+    use crate::testing::temp_dir;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    fn notifier(path: &Path, mode: u32) {
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn notifier_lookup() {
+        let dir = temp_dir("notifiers");
+        let (admin, package) = (dir.join("admin"), dir.join("package"));
+        fs::create_dir_all(&admin).unwrap();
+        fs::create_dir_all(&package).unwrap();
+        let dirs = [admin.clone(), package.clone()];
+
+        notifier(&package.join("mail"), 0o755);
+        notifier(&admin.join("mail"), 0o755);
+        notifier(&package.join("slack"), 0o755);
+        notifier(&admin.join("slack"), 0o644);
+        notifier(&package.join(".hidden"), 0o755);
+        notifier(&dir.join("x"), 0o755);
+
+        // the administrator's notifiers win, unless they can't be run
+        assert_eq!(find_notifier("mail", &dirs), Some(admin.join("mail")));
+        assert_eq!(find_notifier("slack", &dirs), Some(package.join("slack")));
+        assert_eq!(find_notifier("missing", &dirs), None);
+
+        // crontabs can't name files outside the notifiers directories
+        assert_eq!(find_notifier("", &dirs), None);
+        assert_eq!(find_notifier(".hidden", &dirs), None);
+        assert_eq!(find_notifier("../x", &dirs), None);
+        assert_eq!(find_notifier("..", &dirs), None);
+        assert_eq!(find_notifier(&dir.join("x").display().to_string(), &dirs), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn notifier_variables() {
+        let environment = [
+            ("MAILTO", "admin"),
+            ("NOTIFY", "mail,slack"),
+            ("NOTIFY_SLACK_CHANNEL", "#ops"),
+            ("SMTP_PASSWORD", "secret"),
+            ("PATH", "/usr/bin"),
+            ("NOTIFYX", "no"),
+            ("MAILTO_BACKUP", "no"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<BTreeMap<_, _>>();
+
+        let names = notifier_environment(environment).into_keys().collect::<Vec<_>>();
+        assert_eq!(names, vec!["MAILTO", "NOTIFY", "NOTIFY_SLACK_CHANNEL"]);
+    }
+
+    #[test]
+    fn output_tail() {
+        // whole lines are kept when possible
+        assert_eq!(
+            truncate_output("line1\nline2\nline3\n", 8),
+            "[... 12 bytes of output skipped ...]\nline3\n"
+        );
+        // a partial last line is better than nothing
+        assert_eq!(truncate_output("line1\nline2\n", 3), "[... 9 bytes of output skipped ...]\ne2\n");
+        assert_eq!(truncate_output("line1\nlong line", 4), "[... 11 bytes of output skipped ...]\nline");
+        // never in the middle of a character, € is 3 bytes long
+        assert_eq!(truncate_output("ab\u{20ac}cd", 4), "[... 5 bytes of output skipped ...]\ncd");
+        assert_eq!(truncate_output("ab\u{20ac}cd", 5), "[... 2 bytes of output skipped ...]\n\u{20ac}cd");
+
+        assert_eq!(truncate_output("line1\nline2\n", 0), "line1\nline2\n");
+        assert_eq!(truncate_output("line1\nline2\n", 12), "line1\nline2\n");
+        assert_eq!(truncate_output("line1\nline2\n", 11), "[... 6 bytes of output skipped ...]\nline2\n");
+        assert_eq!(truncate_output("", 10), "");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(0), "0.000s");
+        assert_eq!(format_duration(1_500_000), "1.500s");
+        assert_eq!(format_duration(59_999_999), "59.999s");
+        assert_eq!(format_duration(60_000_000), "1min 0s");
+        assert_eq!(format_duration(3_599_000_000), "59min 59s");
+        assert_eq!(format_duration(3_600_000_000), "1h 0min 0s");
+        assert_eq!(format_duration(90_061_000_000), "25h 1min 1s");
+    }
+}
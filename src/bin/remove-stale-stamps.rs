@@ -1,53 +1,305 @@
-extern crate glob;
-extern crate time;
+extern crate docopt;
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
 
 use std::collections::BTreeSet;
-use std::fs::{metadata, remove_file};
-use std::os::unix::fs::MetadataExt;
+use std::fs::{create_dir_all, metadata, read_dir, remove_file};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, SystemTime};
 
-use glob::glob;
-use time::{get_time, Duration};
+use docopt::Docopt;
+use systemd_crontab_generator::timers::{self, History};
 
-static KNOWN_STAMPS: [&str; 6] = [
-    "/var/lib/systemd/timers/stamp-cron-daily.timer",
-    "/var/lib/systemd/timers/stamp-cron-weekly.timer",
-    "/var/lib/systemd/timers/stamp-cron-monthly.timer",
-    "/var/lib/systemd/timers/stamp-cron-quarterly.timer",
-    "/var/lib/systemd/timers/stamp-cron-semi-annually.timer",
-    "/var/lib/systemd/timers/stamp-cron-yearly.timer",
+#[cfg(test)]
+#[path = "../testing.rs"]
+mod testing;
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+static USAGE: &str = r#"
+Usage: remove-stale-stamps [-n] [--max-age <days>] [--stamps-dir <dir>] [--timers-file <file>] [--history-file <file>] [--unit-dir <dir>...]
+       remove-stale-stamps -h | --help
+
+Remove timer stamps left behind by cron timers which don't exist anymore
+
+Only the stamps of the timers the generator recorded in its history file, at any
+of its runs, are considered, whatever their names: the stamps of other packages,
+of the schedule timers, transient timers and template instances are never touched.
+The timers are removed from the history once they have neither a unit nor a stamp.
+
+Options:
+
+  -h, --help                Show this help message and exit.
+  -n, --dry-run             Only report the stamps which would be removed.
+  --max-age <days>          Keep stale stamps updated less than <days> ago,
+                            so that timers removed for a short while don't
+                            lose their state [default: 10].
+  --stamps-dir <dir>        Directory where systemd keeps the stamps of
+                            persistent timers [default: /var/lib/systemd/timers].
+  --timers-file <file>      Timers generated at the last run of the generator,
+                            added to the history in case the generator couldn't
+                            [default: /run/crond.timers].
+  --history-file <file>     Timers generated by all the runs of the generator
+                            [default: /var/lib/systemd-cron/timers].
+  --unit-dir <dir>          Directory to look for timer units, may be given
+                            several times. Defaults to the systemd unit
+                            search path, including generators output.
+"#;
+
+static DEFAULT_UNIT_DIRS: [&str; 8] = [
+    "/etc/systemd/system",
+    "/run/systemd/system",
+    "/run/systemd/generator.early",
+    "/run/systemd/generator",
+    "/run/systemd/generator.late",
+    "/usr/local/lib/systemd/system",
+    "/usr/lib/systemd/system",
+    "/lib/systemd/system",
 ];
 
-static ACTUAL_STAMPS_GLOB: &str = "/var/lib/systemd/timers/stamp-cron-*.timer";
-static TIMER_STAMPS_GLOB: &str = "/run/systemd/generator/cron-*.timer";
+static STAMP_PREFIX: &str = "stamp-";
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    flag_dry_run: bool,
+    flag_max_age: u64,
+    flag_stamps_dir: String,
+    flag_timers_file: String,
+    flag_history_file: String,
+    flag_unit_dir: Vec<String>,
+}
+
+// the stamp of a timer of the generator which doesn't exist anymore
+struct Stale {
+    stamp: PathBuf,
+    timer: String,
+    days: u64,
+    // updated less than --max-age ago
+    recent: bool,
+}
+
+// names of all timer units found in the given directories
+fn known_timers<P: AsRef<Path>, I: IntoIterator<Item = P>>(dirs: I) -> BTreeSet<String> {
+    dirs.into_iter()
+        .filter_map(|dir| read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".timer"))
+        .collect()
+}
+
+fn stamp_age(stamp: &Path) -> Option<Duration> {
+    metadata(stamp)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+}
+
+// the stamps in the directory of the generator's timers, the ones in `generated`,
+// which have no unit anymore, the ones in `live`
+fn stale_stamps(stamps_dir: &Path, generated: &BTreeSet<String>, live: &BTreeSet<String>, max_age: Duration) -> io::Result<Vec<Stale>> {
+    let stamps = read_dir(stamps_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect::<BTreeSet<_>>();
+
+    let mut stale = Vec::new();
+    for stamp in stamps {
+        let timer = match stamp
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(STAMP_PREFIX))
+        {
+            Some(timer) if generated.contains(timer) && !live.contains(timer) => timer.to_owned(),
+            _ => continue,
+        };
+
+        if let Some(age) = stamp_age(&stamp) {
+            stale.push(Stale {
+                stamp,
+                timer,
+                days: age.as_secs() / (24 * 60 * 60),
+                recent: age < max_age,
+            });
+        }
+    }
+    Ok(stale)
+}
+
+// returns false if anything failed
+fn collect(args: &Args, unit_dirs: &[PathBuf]) -> bool {
+    let live = known_timers(unit_dirs);
+    let max_age = Duration::from_secs(args.flag_max_age * 24 * 60 * 60);
+
+    let history_file = Path::new(&args.flag_history_file);
+    let history = history_file
+        .parent()
+        .map_or(Ok(()), create_dir_all)
+        .and_then(|_| History::open(history_file));
+    let mut history = match history {
+        Ok(history) => history,
+        Err(err) => {
+            println!("<3>error reading {}: {}", args.flag_history_file, err);
+            return false;
+        }
+    };
+    match timers::read(Path::new(&args.flag_timers_file)) {
+        Ok(generated) => history.timers.extend(generated),
+        Err(err) => println!("<4>error reading {}: {}", args.flag_timers_file, err),
+    }
+
+    let stamps_dir = Path::new(&args.flag_stamps_dir);
+    let stale = match stale_stamps(stamps_dir, &history.timers, &live, max_age) {
+        Ok(stale) => stale,
+        Err(err) => {
+            println!("<3>error reading {}: {}", args.flag_stamps_dir, err);
+            return false;
+        }
+    };
+
+    let mut ok = true;
+    for stale in stale {
+        let (stamp, timer, days) = (stale.stamp.display(), &stale.timer, stale.days);
+        if stale.recent {
+            println!("<7>keeping {}: no {} unit found, but last updated {} days ago", stamp, timer, days);
+        } else if args.flag_dry_run {
+            println!("would remove {}: no {} unit found, last updated {} days ago", stamp, timer, days);
+        } else if let Err(err) = remove_file(&stale.stamp) {
+            println!("<3>error removing {}: {}", stamp, err);
+            ok = false;
+        } else {
+            println!("removed {}: no {} unit found, last updated {} days ago", stamp, timer, days);
+        }
+    }
 
-fn cleanup<P: AsRef<Path>, I: IntoIterator<Item = P>>(iter: I) {
-    let ten_days_ago = get_time() - Duration::days(10);
-    for stamp in iter {
-        if let Ok(meta) = metadata(&stamp) {
-            if (meta.mtime() as i64) < ten_days_ago.sec {
-                let _ = remove_file(&stamp);
-            }
+    // the timers are forgotten once they have neither a unit nor a stamp
+    if !args.flag_dry_run {
+        history
+            .timers
+            .retain(|timer| live.contains(timer) || stamps_dir.join(format!("{}{}", STAMP_PREFIX, timer)).exists());
+        if let Err(err) = history.save() {
+            println!("<3>error writing {}: {}", args.flag_history_file, err);
+            ok = false;
         }
     }
+
+    ok
 }
 
 fn main() {
-    let stale_stamps = &(&glob(ACTUAL_STAMPS_GLOB)
-        .unwrap()
-        .flat_map(Result::into_iter)
-        .collect::<BTreeSet<_>>()
-        - &glob(TIMER_STAMPS_GLOB)
-            .unwrap()
-            .flat_map(Result::into_iter)
-            .map(|s| {
-                PathBuf::from(
-                    s.to_string_lossy()
-                        .replace("/run/systemd/generator/cron-", "/var/lib/systemd/timers/stamp-cron-"),
-                )
-            })
-            .collect::<BTreeSet<_>>())
-        - &KNOWN_STAMPS.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
-
-    cleanup(&stale_stamps);
+    let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+
+    let mut unit_dirs = args.flag_unit_dir.iter().map(PathBuf::from).collect::<Vec<_>>();
+    if unit_dirs.is_empty() {
+        unit_dirs = DEFAULT_UNIT_DIRS.iter().map(PathBuf::from).collect();
+        unit_dirs.push(PathBuf::from(UNIT_DIR));
+    }
+
+    if !collect(&args, &unit_dirs) {
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, stale_stamps, Args};
+    use crate::testing::temp_dir;
+    use std::collections::BTreeSet;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use systemd_crontab_generator::timers;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn stamp(dir: &Path, timer: &str, days: u64) {
+        let file = File::create(dir.join(format!("stamp-{}", timer))).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(days * DAY)).unwrap();
+    }
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|&name| name.to_owned()).collect()
+    }
+
+    #[test]
+    fn stale_stamps_of_generated_timers() {
+        let dir = temp_dir("stamps");
+        stamp(&dir, "cron-0123.timer", 30);
+        stamp(&dir, "backup-nightly.timer", 30);
+        stamp(&dir, "cron-recent.timer", 3);
+        stamp(&dir, "cron-live.timer", 30);
+        stamp(&dir, "cron-daily.timer", 30);
+        stamp(&dir, "cron-failure@x.timer", 30);
+        stamp(&dir, "apt-daily.timer", 30);
+
+        // any name the generator used, and only those
+        let generated = names(&[
+            "cron-0123.timer",
+            "backup-nightly.timer",
+            "cron-recent.timer",
+            "cron-live.timer",
+            "cron-gone.timer",
+        ]);
+        let live = names(&["cron-live.timer", "cron-daily.timer"]);
+        let stale = stale_stamps(&dir, &generated, &live, Duration::from_secs(10 * DAY)).unwrap();
+
+        let found = stale.iter().map(|s| (&*s.timer, s.days, s.recent)).collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                ("backup-nightly.timer", 30, false),
+                ("cron-0123.timer", 30, false),
+                ("cron-recent.timer", 3, true),
+            ]
+        );
+        assert_eq!(stale[0].stamp, dir.join("stamp-backup-nightly.timer"));
+
+        // a zero max age removes every stale stamp
+        let stale = stale_stamps(&dir, &generated, &live, Duration::from_secs(0)).unwrap();
+        assert!(stale.iter().all(|s| !s.recent));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removed_job() {
+        let dir = temp_dir("removed-job");
+        let (stamps, unit_dirs) = (dir.join("timers"), [dir.join("units")]);
+        fs::create_dir_all(&stamps).unwrap();
+        fs::create_dir_all(&unit_dirs[0]).unwrap();
+        let args = Args {
+            flag_dry_run: false,
+            flag_max_age: 10,
+            flag_stamps_dir: stamps.display().to_string(),
+            flag_timers_file: dir.join("crond.timers").display().to_string(),
+            flag_history_file: dir.join("lib").join("timers").display().to_string(),
+            flag_unit_dir: vec![],
+        };
+        let history = Path::new(&args.flag_history_file);
+
+        // a job is added, runs, and is removed before the collector runs
+        fs::create_dir_all(history.parent().unwrap()).unwrap();
+        timers::record(history, &names(&["cron-kept.timer", "cron-gone.timer"])).unwrap();
+        timers::record(history, &names(&["cron-kept.timer"])).unwrap();
+        fs::write(unit_dirs[0].join("cron-kept.timer"), "").unwrap();
+        stamp(&stamps, "cron-kept.timer", 30);
+        stamp(&stamps, "cron-gone.timer", 30);
+        stamp(&stamps, "apt-daily.timer", 30);
+
+        assert!(collect(&args, &unit_dirs));
+        assert!(!stamps.join("stamp-cron-gone.timer").exists());
+        assert!(stamps.join("stamp-cron-kept.timer").exists());
+        assert!(stamps.join("stamp-apt-daily.timer").exists());
+        assert_eq!(timers::read(history).unwrap(), names(&["cron-kept.timer"]));
+
+        // the timers of the generator's last run are known even if it couldn't record them
+        fs::write(&args.flag_timers_file, "cron-boot.timer\n").unwrap();
+        stamp(&stamps, "cron-boot.timer", 30);
+        assert!(collect(&args, &unit_dirs));
+        assert!(!stamps.join("stamp-cron-boot.timer").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,444 @@
+extern crate docopt;
+extern crate libc;
+extern crate rustc_serialize;
+extern crate systemd_crontab_generator;
+
+use std::fs::{metadata, read_dir};
+use std::io::{self, stderr, stdout, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use docopt::Docopt;
+use systemd_crontab_generator::names::{is_lsb_name, is_runparts_name};
+
+#[cfg(test)]
+#[path = "../testing.rs"]
+mod testing;
+
+static USAGE: &str = r#"
+Usage: run-parts [--test | --list] [--report] [--lsbsysinit] [--exit-on-error] [--timeout <secs>] <dir>
+       run-parts -h | --help
+
+Run the scripts of a directory one after the other, in the lexical order of their names
+
+Only executable files named with letters, digits, underscores and hyphens are run,
+the same as Debian's run-parts. A failed script doesn't prevent the next ones from
+running, unless --exit-on-error is given, and makes the exit status non-zero.
+
+The result of each script is sent to the journal with the fields CRON_SCRIPT,
+CRON_RESULT, CRON_EXIT_STATUS or CRON_SIGNAL, and CRON_DURATION_USEC.
+
+Options:
+
+  -h, --help        Show this help message and exit.
+  --test            Print the scripts which would run, without running them.
+  --list            Print all the files with a valid name, executable or not.
+  --report          Print the name of a script before its first output.
+  --lsbsysinit      Also accept the LSB names, such as _vendor.pkg-job.
+  --exit-on-error   Stop at the first failed script.
+  --timeout <secs>  Kill a script still running after this many seconds.
+"#;
+
+static JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// time a script gets to exit after SIGTERM, before SIGKILL
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_dir: String,
+    flag_test: bool,
+    flag_list: bool,
+    flag_report: bool,
+    flag_lsbsysinit: bool,
+    flag_exit_on_error: bool,
+    flag_timeout: Option<u64>,
+}
+
+enum Outcome {
+    Success,
+    ExitCode(i32),
+    Signal(i32),
+    Timeout,
+    Error(io::Error),
+}
+
+impl Outcome {
+    // the same names as the Result property of systemd services
+    fn result(&self) -> &'static str {
+        match *self {
+            Outcome::Success => "success",
+            Outcome::ExitCode(_) => "exit-code",
+            Outcome::Signal(_) => "signal",
+            Outcome::Timeout => "timeout",
+            Outcome::Error(_) => "resources",
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Outcome::Success => "succeeded".to_owned(),
+            Outcome::ExitCode(code) => format!("exited with status {}", code),
+            Outcome::Signal(signal) => format!("was killed by signal {}", signal),
+            Outcome::Timeout => "timed out".to_owned(),
+            Outcome::Error(ref err) => format!("failed to start: {}", err),
+        }
+    }
+}
+
+// files with a valid name, in the order they run
+fn list_parts(dir: &Path, lsbsysinit: bool) -> io::Result<Vec<PathBuf>> {
+    let mut parts = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let valid = if lsbsysinit { is_lsb_name(name) } else { is_runparts_name(name) };
+        if valid && path.is_file() {
+            parts.push(path);
+        }
+    }
+    parts.sort();
+    Ok(parts)
+}
+
+fn is_executable(path: &Path) -> bool {
+    metadata(path).map(|meta| meta.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+// native journal protocol: one datagram of KEY=value lines, values with
+// a newline are sent as the key, the little endian length and the raw value
+fn journal_send(fields: &[(&str, String)]) -> io::Result<()> {
+    let mut datagram = Vec::new();
+    for &(name, ref value) in fields {
+        datagram.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+    UnixDatagram::unbound()?.send_to(&datagram, JOURNAL_SOCKET).map(|_| ())
+}
+
+fn log_outcome(script: &Path, outcome: &Outcome, duration: Duration) {
+    let message = format!("{} {}", script.display(), outcome.describe());
+    let priority = match *outcome {
+        Outcome::Success => 6,
+        _ => 3,
+    };
+
+    let mut fields = vec![
+        ("MESSAGE", message.clone()),
+        ("PRIORITY", priority.to_string()),
+        ("SYSLOG_IDENTIFIER", "run-parts".to_owned()),
+        ("CRON_SCRIPT", script.display().to_string()),
+        ("CRON_RESULT", outcome.result().to_owned()),
+        ("CRON_DURATION_USEC", (duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())).to_string()),
+    ];
+    match *outcome {
+        Outcome::ExitCode(code) => fields.push(("CRON_EXIT_STATUS", code.to_string())),
+        Outcome::Signal(signal) => fields.push(("CRON_SIGNAL", signal.to_string())),
+        _ => (),
+    }
+
+    // without a journal, only failures are worth a line in the output
+    if journal_send(&fields).is_err() && priority < 6 {
+        println!("<{}>{}", priority, message);
+    }
+}
+
+// copies the output of a script, preceded by its name the first time it writes anything
+fn report<R, W>(mut output: R, mut out: W, script: &Path, reported: &Arc<AtomicBool>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let script = script.display().to_string();
+    let reported = reported.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            let size = match output.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(size) => size,
+            };
+            if !reported.swap(true, Ordering::SeqCst) {
+                let _ = writeln!(out, "{}:", script);
+            }
+            let _ = out.write_all(&buf[..size]).and_then(|_| out.flush());
+        }
+    })
+}
+
+// the script gets its own process group, so that a timeout kills its children too
+fn kill_group(child: &Child, signal: libc::c_int) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), signal);
+    }
+}
+
+fn wait_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return child.wait().map(Some),
+    };
+
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        sleep(POLL_INTERVAL);
+    }
+
+    kill_group(child, libc::SIGTERM);
+    let killed = Instant::now();
+    while killed.elapsed() < KILL_TIMEOUT {
+        if child.try_wait()?.is_some() {
+            return Ok(None);
+        }
+        sleep(POLL_INTERVAL);
+    }
+    kill_group(child, libc::SIGKILL);
+    child.wait().map(|_| None)
+}
+
+fn run_part(script: &Path, args: &Args) -> Outcome {
+    let mut command = Command::new(script);
+    command.process_group(0);
+    if args.flag_report {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => return Outcome::Error(err),
+    };
+
+    let reported = Arc::new(AtomicBool::new(false));
+    let mut relays = Vec::new();
+    if let Some(output) = child.stdout.take() {
+        relays.push(report(output, stdout(), script, &reported));
+    }
+    if let Some(output) = child.stderr.take() {
+        relays.push(report(output, stderr(), script, &reported));
+    }
+
+    let status = wait_timeout(&mut child, args.flag_timeout.map(Duration::from_secs));
+    for relay in relays {
+        let _ = relay.join();
+    }
+
+    match status {
+        Ok(Some(status)) => match (status.code(), status.signal()) {
+            (Some(0), _) => Outcome::Success,
+            (Some(code), _) => Outcome::ExitCode(code),
+            (None, Some(signal)) => Outcome::Signal(signal),
+            (None, None) => Outcome::ExitCode(-1),
+        },
+        Ok(None) => Outcome::Timeout,
+        Err(err) => Outcome::Error(err),
+    }
+}
+
+// the number of scripts run, and the names of the failed ones
+fn run_parts(parts: &[PathBuf], args: &Args) -> (usize, Vec<String>) {
+    let mut failed = Vec::new();
+    let mut ran = 0;
+    for script in parts.iter().filter(|part| is_executable(part)) {
+        let started = Instant::now();
+        let outcome = run_part(script, args);
+        log_outcome(script, &outcome, started.elapsed());
+
+        ran += 1;
+        if let Outcome::Success = outcome {
+            continue;
+        }
+        failed.push(script.file_name().and_then(|n| n.to_str()).unwrap_or("").to_owned());
+        if args.flag_exit_on_error {
+            break;
+        }
+    }
+    (ran, failed)
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
+
+    let dir = Path::new(&args.arg_dir);
+    let parts = match list_parts(dir, args.flag_lsbsysinit) {
+        Ok(parts) => parts,
+        Err(err) => {
+            println!("<3>error reading directory {}: {}", dir.display(), err);
+            exit(1);
+        }
+    };
+
+    if args.flag_list || args.flag_test {
+        for part in parts.iter().filter(|part| args.flag_list || is_executable(part)) {
+            println!("{}", part.display());
+        }
+        return;
+    }
+
+    // the same default as Debian's run-parts, whatever the umask of the caller
+    unsafe {
+        libc::umask(0o022);
+    }
+
+    let (ran, failed) = run_parts(&parts, &args);
+    if !failed.is_empty() {
+        println!("<3>{} of {} scripts of {} failed: {}", failed.len(), ran, dir.display(), failed.join(", "));
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_parts, report, run_part, run_parts, Args, Outcome};
+    use crate::testing::temp_dir;
+    use std::fs;
+    use std::io::{self, Cursor, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn args(dir: &Path) -> Args {
+        Args {
+            arg_dir: dir.display().to_string(),
+            flag_test: false,
+            flag_list: false,
+            flag_report: false,
+            flag_lsbsysinit: false,
+            flag_exit_on_error: false,
+            flag_timeout: None,
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn names() {
+        let dir = temp_dir("names");
+        for name in &["job", "_vendor.pkg-job", "job.dpkg-old", "job.sh", "job~"] {
+            script(&dir, name, "true");
+        }
+        fs::create_dir_all(dir.join("subdir")).unwrap();
+
+        let names = |lsbsysinit| {
+            list_parts(&dir, lsbsysinit)
+                .unwrap()
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(false), ["job"]);
+        assert_eq!(names(true), ["_vendor.pkg-job", "job"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exit_on_error() {
+        let dir = temp_dir("exit-on-error");
+        let marker = dir.join("marker");
+        let parts = vec![
+            script(&dir, "10-fail", "exit 3"),
+            script(&dir, "20-ok", &format!("touch {}", marker.display())),
+        ];
+        let mut args = args(&dir);
+
+        assert_eq!(run_parts(&parts, &args), (2, vec!["10-fail".to_owned()]));
+        assert!(marker.exists());
+
+        fs::remove_file(&marker).unwrap();
+        args.flag_exit_on_error = true;
+        assert_eq!(run_parts(&parts, &args), (1, vec!["10-fail".to_owned()]));
+        assert!(!marker.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outcomes() {
+        let dir = temp_dir("outcomes");
+        let args = args(&dir);
+        assert!(matches!(run_part(&script(&dir, "ok", "true"), &args), Outcome::Success));
+        assert!(matches!(run_part(&script(&dir, "fail", "exit 3"), &args), Outcome::ExitCode(3)));
+        assert!(matches!(run_part(&script(&dir, "killed", "kill -9 $$"), &args), Outcome::Signal(9)));
+        assert!(matches!(run_part(&dir.join("missing"), &args), Outcome::Error(_)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_the_process_group() {
+        let dir = temp_dir("timeout");
+        let pid_file = dir.join("pid");
+        let part = script(&dir, "slow", &format!("sleep 60 &\necho $! > {}\nwait", pid_file.display()));
+        let mut args = args(&dir);
+        args.flag_timeout = Some(1);
+
+        let started = Instant::now();
+        assert!(matches!(run_part(&part, &args), Outcome::Timeout));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // the background sleep is gone too, or at most a zombie left to init
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn report_names_the_script_once() {
+        let reported = Arc::new(AtomicBool::new(false));
+        let output = Output::default();
+        let script = Path::new("/etc/cron.daily/job");
+
+        report(Cursor::new(b"out\n".to_vec()), output.clone(), script, &reported)
+            .join()
+            .unwrap();
+        report(Cursor::new(b"err\n".to_vec()), output.clone(), script, &reported)
+            .join()
+            .unwrap();
+        assert_eq!(&*output.0.lock().unwrap(), b"/etc/cron.daily/job:\nout\nerr\n");
+
+        // silent scripts aren't named
+        let reported = Arc::new(AtomicBool::new(false));
+        let output = Output::default();
+        report(Cursor::new(Vec::new()), output.clone(), script, &reported).join().unwrap();
+        assert!(output.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+//! Evaluator for the systemd calendar events emitted by the generator,
+//! so that elapse times can be computed without a running systemd.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+// give up looking for the next elapse after this many days,
+// events like `*-2-30` never elapse
+const MAX_DAYS: u32 = 5 * 366;
+
+static DAY_OF_WEEK_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// TZ is shared by the whole process
+static TIMEZONE_LOCK: Mutex<()> = Mutex::new(());
+
+fn set_tz(value: Option<&str>) {
+    match value {
+        Some(tz) => env::set_var("TZ", tz),
+        None => env::remove_var("TZ"),
+    }
+    time::tzset();
+}
+
+/// Runs `f` with the time zone used by the evaluator and local time formatting
+/// switched to `timezone`, then restores `TZ`. `None` keeps the current one.
+pub fn with_timezone<T, F: FnOnce() -> T>(timezone: Option<&str>, f: F) -> T {
+    let _lock = TIMEZONE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let previous = env::var("TZ").ok();
+    if timezone.is_some() {
+        set_tz(timezone);
+    }
+
+    let result = f();
+
+    if timezone.is_some() {
+        set_tz(previous.as_deref());
+    }
+    result
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CalendarEventParseError(String);
+
+impl Display for CalendarEventParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "invalid calendar event: {}", self.0)
+    }
+}
+
+impl Error for CalendarEventParseError {}
+
+/// A parsed `OnCalendar=` value, as far as the generator uses them:
+/// weekdays, `*` years, lists, ranges and repetitions, and a time zone.
+#[derive(Debug, PartialEq)]
+pub struct CalendarEvent {
+    dows: BTreeSet<u8>,
+    mons: BTreeSet<u8>,
+    days: BTreeSet<u8>,
+    hrs: BTreeSet<u8>,
+    mins: BTreeSet<u8>,
+    secs: BTreeSet<u8>,
+    pub timezone: Option<String>,
+}
+
+/// The explicit form of a calendar shorthand such as `daily`, `None` for other events.
+pub fn expand_shorthand(event: &str) -> Option<&'static str> {
+    match event.trim() {
+        "minutely" => Some("*-*-* *:*:00"),
+        "hourly" => Some("*-*-* *:00:00"),
+        "daily" => Some("*-*-* 00:00:00"),
+        "weekly" => Some("Mon *-*-* 00:00:00"),
+        "monthly" => Some("*-*-01 00:00:00"),
+        "quarterly" => Some("*-01,04,07,10-01 00:00:00"),
+        "semiannually" => Some("*-01,07-01 00:00:00"),
+        "yearly" | "annually" => Some("*-01-01 00:00:00"),
+        _ => None,
+    }
+}
+
+impl FromStr for CalendarEvent {
+    type Err = CalendarEventParseError;
+
+    fn from_str(s: &str) -> Result<CalendarEvent, CalendarEventParseError> {
+        let expanded = expand_shorthand(s).unwrap_or_else(|| s.trim());
+
+        let mut event = CalendarEvent {
+            dows: (0..7).collect(),
+            mons: (1..=12).collect(),
+            days: (1..=31).collect(),
+            hrs: (0..=23).collect(),
+            mins: (0..=59).collect(),
+            secs: [0].iter().cloned().collect(),
+            timezone: None,
+        };
+
+        let (mut date_seen, mut time_seen) = (false, false);
+        for token in expanded.split_whitespace() {
+            let first = token.chars().next().unwrap_or(' ');
+
+            if token.contains(':') && !time_seen {
+                let mut parts = token.split(':');
+                event.hrs = parse_field(parts.next(), 0..=23)?;
+                event.mins = parse_field(parts.next(), 0..=59)?;
+                if let Some(secs) = parts.next() {
+                    event.secs = parse_field(Some(secs), 0..=59)?;
+                }
+                if parts.next().is_some() {
+                    return Err(CalendarEventParseError(format!("invalid time {}", token)));
+                }
+                time_seen = true;
+            } else if (first == '*' || first.is_ascii_digit()) && !date_seen {
+                let parts = token.split('-').collect::<Vec<_>>();
+                let (mons, days) = match *parts {
+                    ["*", mons, days] | [mons, days] => (mons, days),
+                    [_, _, _] => return Err(CalendarEventParseError(format!("unsupported year in {}", token))),
+                    _ => return Err(CalendarEventParseError(format!("invalid date {}", token))),
+                };
+                event.mons = parse_field(Some(mons), 1..=12)?;
+                event.days = parse_field(Some(days), 1..=31)?;
+                date_seen = true;
+            } else if !date_seen && !time_seen && event.timezone.is_none() && event.dows.len() == 7 {
+                event.dows = parse_dows(token)?;
+            } else if event.timezone.is_none() {
+                event.timezone = Some(token.to_owned());
+            } else {
+                return Err(CalendarEventParseError(format!("unexpected {}", token)));
+            }
+        }
+
+        Ok(event)
+    }
+}
+
+fn parse_number(value: &str, range: &RangeInclusive<u8>) -> Result<u8, CalendarEventParseError> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|v| range.contains(v))
+        .ok_or_else(|| CalendarEventParseError(format!("invalid value {}", value)))
+}
+
+fn parse_field(field: Option<&str>, range: RangeInclusive<u8>) -> Result<BTreeSet<u8>, CalendarEventParseError> {
+    let field = field.ok_or_else(|| CalendarEventParseError("missing field".to_owned()))?;
+    let mut output = BTreeSet::new();
+
+    for item in field.split(',') {
+        let mut parts = item.splitn(2, '/');
+        let (from, to, ranged) = match parts.next().unwrap_or("") {
+            "*" => (*range.start(), *range.end(), true),
+            value => match value.find("..") {
+                Some(pos) => (parse_number(&value[..pos], &range)?, parse_number(&value[pos + 2..], &range)?, true),
+                None => (parse_number(value, &range)?, parse_number(value, &range)?, false),
+            },
+        };
+        if from > to {
+            return Err(CalendarEventParseError(format!("invalid range {}", item)));
+        }
+        let (to, step) = match parts.next() {
+            // `a/b` means every b starting at a, `a..c/b` stops at c
+            Some(step) => (if ranged { to } else { *range.end() }, parse_number(step, &(1..=u8::MAX))?),
+            None => (to, 1),
+        };
+        output.extend((from..=to).step_by(step as usize));
+    }
+
+    Ok(output)
+}
+
+fn parse_dow(name: &str) -> Result<u8, CalendarEventParseError> {
+    let lower = name.to_ascii_lowercase();
+    DAY_OF_WEEK_NAMES
+        .iter()
+        .position(|&n| lower.get(..3) == Some(n))
+        .map(|pos| pos as u8)
+        .ok_or_else(|| CalendarEventParseError(format!("invalid day of week {}", name)))
+}
+
+fn parse_dows(field: &str) -> Result<BTreeSet<u8>, CalendarEventParseError> {
+    let mut output = BTreeSet::new();
+
+    for item in field.split(',') {
+        match item.find("..").map(|pos| (pos, 2)).or_else(|| item.find('-').map(|pos| (pos, 1))) {
+            Some((pos, len)) => {
+                // ranges may wrap around the end of week, like Sat..Mon
+                let (from, to) = (parse_dow(&item[..pos])?, parse_dow(&item[pos + len..])?);
+                let mut dow = from;
+                loop {
+                    output.insert(dow);
+                    if dow == to {
+                        break;
+                    }
+                    dow = (dow + 1) % 7;
+                }
+            }
+            None => {
+                output.insert(parse_dow(item)?);
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+fn localtime(timestamp: libc::time_t) -> libc::tm {
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&timestamp, &mut tm);
+        tm
+    }
+}
+
+fn mktime(tm: &mut libc::tm) -> libc::time_t {
+    tm.tm_isdst = -1;
+    unsafe { libc::mktime(tm) }
+}
+
+impl CalendarEvent {
+    /// First elapse strictly after the given UNIX timestamp, in the current time zone.
+    pub fn next_elapse(&self, after: i64) -> Option<i64> {
+        let mut day = localtime(after as libc::time_t);
+        day.tm_hour = 12;
+        day.tm_min = 0;
+        day.tm_sec = 0;
+
+        for _ in 0..MAX_DAYS {
+            // normalizes the date and computes the day of week
+            mktime(&mut day);
+
+            if self.mons.contains(&((day.tm_mon + 1) as u8))
+                && self.days.contains(&(day.tm_mday as u8))
+                && self.dows.contains(&(day.tm_wday as u8))
+            {
+                for &hr in &self.hrs {
+                    for &min in &self.mins {
+                        for &sec in &self.secs {
+                            let mut tm = day;
+                            tm.tm_hour = hr as libc::c_int;
+                            tm.tm_min = min as libc::c_int;
+                            tm.tm_sec = sec as libc::c_int;
+                            let timestamp = mktime(&mut tm) as i64;
+                            if timestamp > after {
+                                return Some(timestamp);
+                            }
+                        }
+                    }
+                }
+            }
+
+            day.tm_mday += 1;
+        }
+
+        None
+    }
+
+    /// The next `count` elapses after the given UNIX timestamp.
+    pub fn elapses(&self, after: i64, count: usize) -> Vec<i64> {
+        let mut output = Vec::with_capacity(count);
+        let mut last = after;
+        while output.len() < count {
+            match self.next_elapse(last) {
+                Some(next) => {
+                    output.push(next);
+                    last = next;
+                }
+                None => break,
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_shorthand, with_timezone, CalendarEvent};
+
+    // 2024-01-01 12:00:00 UTC, a Monday
+    const NEW_YEAR_NOON: i64 = 1_704_110_400;
+
+    fn elapses(event: &str, timezone: &str, after: i64, count: usize) -> Vec<i64> {
+        let event = event.parse::<CalendarEvent>().unwrap();
+        with_timezone(Some(timezone), || event.elapses(after, count))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(expand_shorthand(" quarterly "), Some("*-01,04,07,10-01 00:00:00"));
+        assert_eq!(expand_shorthand("quaterly"), None);
+        assert_eq!("weekly".parse::<CalendarEvent>(), "Mon *-*-* 00:00:00".parse::<CalendarEvent>());
+        assert_eq!("*-*-* 0:0:0".parse::<CalendarEvent>(), "*-*-* 00:00".parse::<CalendarEvent>());
+        assert_eq!("Sat..Mon 12:00".parse::<CalendarEvent>(), "Sat,Sun,Mon 12:00".parse::<CalendarEvent>());
+        assert_eq!("*-*-* 1,2:00 Europe/Berlin".parse::<CalendarEvent>().unwrap().timezone, Some("Europe/Berlin".to_owned()));
+        assert_eq!("*-*-* 9..17/4:00".parse::<CalendarEvent>(), "*-*-* 9,13,17:00".parse::<CalendarEvent>());
+        assert_eq!("*-*-* 20/2:00".parse::<CalendarEvent>(), "*-*-* 20,22:00".parse::<CalendarEvent>());
+        assert_eq!("*-*-* 9..9:00".parse::<CalendarEvent>(), "*-*-* 9:00".parse::<CalendarEvent>());
+        assert_eq!("monday 12:00".parse::<CalendarEvent>(), "Mon 12:00".parse::<CalendarEvent>());
+
+        for invalid in &[
+            "2024-01-01 00:00",
+            "*-13-01 00:00",
+            "*-*-* 24:00",
+            "*-*-* 1:2:3:4",
+            "Foo 00:00",
+            "*-*-*/0 00:00",
+            "00:00 UTC x",
+            "*-*-* 17..9:00",
+            "*-*-* 17..9/2:00",
+            "Mo 00:00",
+            "L\u{fc} 00:00",
+            "\u{e9}t\u{e9} 00:00",
+            "Mo\u{e9}..Fri 00:00",
+        ] {
+            assert!(invalid.parse::<CalendarEvent>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn next_elapse() {
+        assert_eq!(elapses("daily", "UTC", NEW_YEAR_NOON, 1), [1_704_153_600]);
+        assert_eq!(elapses("*-*-* *:*:0/15", "UTC", NEW_YEAR_NOON, 1), [NEW_YEAR_NOON + 15]);
+        // strictly after
+        assert_eq!(elapses("*-*-* 12:00:00", "UTC", NEW_YEAR_NOON, 1), [NEW_YEAR_NOON + 86_400]);
+        assert_eq!(elapses("quarterly", "UTC", NEW_YEAR_NOON, 3), [1_711_929_600, 1_719_792_000, 1_727_740_800]);
+        assert_eq!(elapses("*-2-29 00:00", "UTC", NEW_YEAR_NOON, 2), [1_709_164_800, 1_835_395_200]);
+        assert_eq!(elapses("*-2-30 00:00", "UTC", NEW_YEAR_NOON, 1), []);
+    }
+
+    #[test]
+    fn days_of_month_and_week() {
+        // both have to match, unlike cron
+        assert_eq!(elapses("Mon *-*-13 00:00", "UTC", NEW_YEAR_NOON, 1), [1_715_558_400]);
+        // from Friday 2024-01-05, wrapping around the end of week
+        assert_eq!(elapses("Sat..Mon 00:00", "UTC", 1_704_456_000, 4), [1_704_499_200, 1_704_585_600, 1_704_672_000, 1_705_104_000]);
+    }
+
+    #[test]
+    fn daylight_saving_time() {
+        // midnights 23 hours apart when the clocks go forward, 25 hours when they go back
+        assert_eq!(elapses("daily", "Europe/Berlin", 1_711_796_400, 2), [1_711_839_600, 1_711_922_400]);
+        assert_eq!(elapses("daily", "Europe/Berlin", 1_729_936_800, 2), [1_729_980_000, 1_730_070_000]);
+        // the event's own zone is up to the caller, the same instant in UTC
+        assert_eq!(elapses("daily", "UTC", 1_711_796_400, 1), [1_711_843_200]);
+    }
+
+    #[test]
+    fn timezone_is_restored() {
+        let before = std::env::var("TZ").ok();
+        with_timezone(Some("Asia/Tokyo"), || assert_eq!(std::env::var("TZ").ok().as_deref(), Some("Asia/Tokyo")));
+        assert_eq!(std::env::var("TZ").ok(), before);
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::{metadata, symlink_metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use systemd_crontab_generator::identity;
+use systemd_crontab_generator::sysroot;
+
+static SHADOW_FILE: &str = "/etc/shadow";
+
+// name, password, last change, min, max, warn, inactive, expiration and reserved,
+// the last one is left out by some tools
+const SHADOW_FIELDS: usize = 8;
+
+// the same as vixie cron: only root may add system jobs, and users
+// only get jobs in their own name
+pub fn check_system_crontab(path: &Path) -> Result<(), String> {
+    check_system_file(path, 0)
+}
+
+// system crontabs and scripts, owned by `owner`, root except in tests
+pub fn check_system_file(path: &Path, owner: libc::uid_t) -> Result<(), String> {
+    let meta = metadata(path).map_err(|err| err.to_string())?;
+    if meta.uid() != owner {
+        let owner = if owner == 0 { "root".to_owned() } else { format!("uid {}", owner) };
+        return Err(format!("owned by uid {} instead of {}", meta.uid(), owner));
+    }
+    if meta.mode() & 0o022 != 0 {
+        return Err(format!("writable by group or others (mode {:o})", meta.mode() & 0o7777));
+    }
+    Ok(())
+}
+
+pub fn check_user_crontab(path: &Path, user: &str) -> Result<(), String> {
+    // crontab(1) only installs regular files in the spool
+    let meta = symlink_metadata(path).map_err(|err| err.to_string())?;
+    if !meta.file_type().is_file() {
+        return Err("not a regular file".to_owned());
+    }
+
+    // users of directory services may not be known yet, the spool is only
+    // writable through crontab(1) which names crontabs after their owner
+    if let Some(entry) = identity::user_by_name(user) {
+        if meta.uid() != entry.uid() {
+            return Err(format!("owned by uid {} instead of {}", meta.uid(), user));
+        }
+    }
+    if meta.mode() & 0o022 != 0 {
+        return Err(format!("writable by group or others (mode {:o})", meta.mode() & 0o7777));
+    }
+
+    match account_status(user) {
+        Some(status) => Err(format!("account of {} is {}", user, status)),
+        None => Ok(()),
+    }
+}
+
+/// Whether the account is locked or expired in /etc/shadow, root is never.
+///
+/// Only passwords locked with `passwd -l` count, accounts which never had a password,
+/// like `!` or `!*`, are the usual system users.
+pub fn account_status(user: &str) -> Option<&'static str> {
+    if user == "root" {
+        return None;
+    }
+
+    shadow_status(&sysroot::path(SHADOW_FILE), user, time::get_time().sec / (24 * 60 * 60))
+}
+
+// `today` in days since the epoch, like the dates of shadow files,
+// an unreadable shadow file locks no one
+fn shadow_status(path: &Path, user: &str, today: i64) -> Option<&'static str> {
+    let entries = identity::read_entries(path, SHADOW_FIELDS)?;
+    let entry = entries.into_iter().find(|entry| entry[0] == user)?;
+    let passwd = &*entry[1];
+    if passwd.starts_with('!') && !matches!(&passwd[1..], "" | "*" | "!") {
+        return Some("locked");
+    }
+
+    match entry[7].parse::<i64>() {
+        Ok(expires) if expires > 0 && today >= expires => Some("expired"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account_status, check_system_crontab, check_user_crontab, identity, shadow_status};
+    use crate::testing::temp_dir;
+    use std::fs;
+    use std::os::unix::fs::{chown, symlink, PermissionsExt};
+    use std::path::{Path, PathBuf};
+
+    fn crontab(dir: &Path, name: &str, mode: u32) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, "@daily true\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    fn is_root() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    #[test]
+    fn system_crontabs() {
+        let dir = temp_dir("system");
+
+        let path = crontab(&dir, "job", 0o644);
+        assert_eq!(check_system_crontab(&path).is_ok(), is_root());
+        for &mode in &[0o664, 0o646, 0o666] {
+            let path = crontab(&dir, "job", mode);
+            let err = check_system_crontab(&path).unwrap_err();
+            assert!(err.contains("writable by group or others") || !is_root(), "{}", err);
+        }
+        if is_root() {
+            let path = crontab(&dir, "job", 0o600);
+            chown(&path, Some(1), None).unwrap();
+            assert_eq!(check_system_crontab(&path), Err("owned by uid 1 instead of root".to_owned()));
+        }
+        assert!(check_system_crontab(&dir.join("missing")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn user_crontabs() {
+        let dir = temp_dir("user");
+        let user = identity::user_by_uid(unsafe { libc::geteuid() }).unwrap();
+        let user = user.name();
+
+        let path = crontab(&dir, user, 0o600);
+        assert_eq!(check_user_crontab(&path, user), Ok(()));
+        let path = crontab(&dir, user, 0o620);
+        assert_eq!(check_user_crontab(&path, user), Err("writable by group or others (mode 620)".to_owned()));
+
+        let link = dir.join("link");
+        symlink(&path, &link).unwrap();
+        assert_eq!(check_user_crontab(&link, user), Err("not a regular file".to_owned()));
+
+        if is_root() {
+            let path = crontab(&dir, "daemon", 0o600);
+            assert!(check_user_crontab(&path, "daemon")
+                .unwrap_err()
+                .starts_with("owned by uid 0 instead of daemon"));
+        }
+
+        // unknown users may come from a directory service which isn't up yet
+        let path = crontab(&dir, "no-such-user", 0o600);
+        assert_eq!(check_user_crontab(&path, "no-such-user"), Ok(()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn accounts() {
+        let dir = temp_dir("shadow");
+        let shadow = dir.join("shadow");
+        fs::write(
+            &shadow,
+            "alice:$6$salt$hash:19000:0:99999:7:::\n\
+             locked:!$6$salt$hash:19000:0:99999:7:::\n\
+             daemon:*:19000:0:99999:7:::\n\
+             nopass:!:19000::::::\n\
+             never:!*:19000::::::\n\
+             expires:$6$salt$hash:19000:0:99999:7::20000:\n",
+        )
+        .unwrap();
+
+        assert_eq!(shadow_status(&shadow, "alice", 20000), None);
+        assert_eq!(shadow_status(&shadow, "locked", 20000), Some("locked"));
+        assert_eq!(shadow_status(&shadow, "daemon", 20000), None);
+        assert_eq!(shadow_status(&shadow, "nopass", 20000), None);
+        assert_eq!(shadow_status(&shadow, "never", 20000), None);
+        assert_eq!(shadow_status(&shadow, "expires", 19999), None);
+        assert_eq!(shadow_status(&shadow, "expires", 20000), Some("expired"));
+        assert_eq!(shadow_status(&shadow, "unknown", 20000), None);
+        assert_eq!(account_status("root"), None);
+
+        // bad lines are skipped, not the whole file
+        fs::write(
+            &shadow,
+            "\n\
+             short:!$6$salt$hash\n\
+             locked:!$6$salt$hash:19000:0:99999:7:::\n",
+        )
+        .unwrap();
+        assert_eq!(shadow_status(&shadow, "short", 20000), None);
+        assert_eq!(shadow_status(&shadow, "locked", 20000), Some("locked"));
+
+        // nor can an unreadable file lock anyone
+        assert_eq!(shadow_status(&dir, "locked", 20000), None);
+        assert_eq!(shadow_status(&dir.join("missing"), "locked", 20000), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Switches of the kernel command line, to turn cron off or debug it for one boot
+//! without editing the files of a possibly broken root.
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::sync::OnceLock;
+
+use systemd_crontab_generator::sysroot;
+
+static PROC_CMDLINE: &str = "/proc/cmdline";
+// the same override as systemd's, to try the switches without rebooting
+static CMDLINE_ENV: &str = "SYSTEMD_PROC_CMDLINE";
+
+static SWITCHES: OnceLock<Switches> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Switches {
+    /// `systemd.cron=0` generates nothing, and keeps cron.target from starting.
+    pub enabled: bool,
+    /// `systemd.cron.reboot=0` skips the @reboot jobs.
+    pub reboot: bool,
+    /// `systemd.cron.debug` logs everything, whatever `$KERNLOG_LEVEL` says.
+    pub debug: bool,
+}
+
+impl Default for Switches {
+    fn default() -> Switches {
+        Switches {
+            enabled: true,
+            reboot: true,
+            debug: false,
+        }
+    }
+}
+
+// the booleans of systemd, a switch without a value is on
+fn parse_boolean(value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("1") | Some("yes") | Some("y") | Some("true") | Some("t") | Some("on") => Some(true),
+        Some("0") | Some("no") | Some("n") | Some("false") | Some("f") | Some("off") => Some(false),
+        _ => None,
+    }
+}
+
+impl Switches {
+    /// Parse the switches, with the values which aren't booleans.
+    pub fn parse(cmdline: &str) -> (Switches, Vec<String>) {
+        let mut switches = Switches::default();
+        let mut warnings = Vec::new();
+
+        for word in cmdline.split_whitespace() {
+            let mut parts = word.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let switch = match key {
+                "systemd.cron" => &mut switches.enabled,
+                "systemd.cron.reboot" => &mut switches.reboot,
+                "systemd.cron.debug" => &mut switches.debug,
+                _ => continue,
+            };
+            match parse_boolean(parts.next()) {
+                Some(value) => *switch = value,
+                None => warnings.push(format!("ignoring {}: not a boolean", word)),
+            }
+        }
+
+        (switches, warnings)
+    }
+
+    /// Read the command line of the running kernel, which says nothing about another root.
+    pub fn read() -> (Switches, Vec<String>) {
+        if sysroot::is_set() {
+            return (Switches::default(), Vec::new());
+        }
+        if let Ok(cmdline) = env::var(CMDLINE_ENV) {
+            return Switches::parse(&cmdline);
+        }
+
+        let mut cmdline = String::new();
+        match File::open(PROC_CMDLINE).and_then(|mut file| file.read_to_string(&mut cmdline)) {
+            Ok(_) => Switches::parse(&cmdline),
+            Err(err) => (Switches::default(), vec![format!("error reading {}: {}", PROC_CMDLINE, err)]),
+        }
+    }
+}
+
+/// Read the switches of this boot, returning the problems found for the caller
+/// to log once the logger is set up.
+pub fn init() -> Vec<String> {
+    let mut warnings = Vec::new();
+    SWITCHES.get_or_init(|| {
+        let (switches, problems) = Switches::read();
+        warnings = problems;
+        switches
+    });
+    warnings
+}
+
+/// The switches of this boot, read on first use.
+pub fn get() -> &'static Switches {
+    SWITCHES.get_or_init(|| Switches::read().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Switches;
+
+    #[test]
+    fn defaults() {
+        let (switches, warnings) = Switches::parse("BOOT_IMAGE=/vmlinuz root=/dev/sda1 ro quiet systemd.unit=rescue.target\n");
+        assert!(warnings.is_empty());
+        assert!(switches.enabled && switches.reboot && !switches.debug);
+    }
+
+    #[test]
+    fn switches() {
+        let (switches, warnings) = Switches::parse("quiet systemd.cron=0 systemd.cron.reboot=off systemd.cron.debug");
+        assert!(warnings.is_empty());
+        assert!(!switches.enabled && !switches.reboot && switches.debug);
+
+        // the last one wins, the same as systemd
+        let (switches, _) = Switches::parse("systemd.cron=no systemd.cron=yes systemd.cron.debug=1 systemd.cron.debug=false");
+        assert!(switches.enabled && !switches.debug);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let (switches, warnings) = Switches::parse("systemd.cron=maybe systemd.cron.reboot= systemd.crontab=0");
+        assert!(switches.enabled && switches.reboot);
+        assert_eq!(
+            warnings,
+            vec![
+                "ignoring systemd.cron=maybe: not a boolean",
+                "ignoring systemd.cron.reboot=: not a boolean"
+            ]
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! Runtime settings of the generator and the helpers, read from `systemd-cron/config.toml`
+//! in the configuration directory chosen at build time.
+//!
+//! Only the subset of TOML these settings need is understood: `key = value` lines
+//! with strings, booleans and arrays of strings, and comments. The build settings
+//! are the defaults of missing keys, unless the systemd version decides.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::sysroot;
+use crate::systemd::Support;
+
+#[allow(dead_code)]
+mod build {
+    include!(concat!(env!("OUT_DIR"), "/config.rs"));
+}
+
+// relative to the build time CONF_DIR, the same as the other helpers settings
+static CONFIG_FILE: &str = "systemd-cron/config.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Default of the `PERSISTENT` crontab variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persistence {
+    /// Only jobs run on a period, such as `@daily` and anacron jobs, catch up.
+    Auto,
+    Yes,
+    No,
+}
+
+impl Persistence {
+    /// The same values as `PERSISTENT`, anything unknown means no.
+    pub fn from_value(value: &str) -> Persistence {
+        match value {
+            "yes" | "true" | "1" => Persistence::Yes,
+            "auto" | "" => Persistence::Auto,
+            _ => Persistence::No,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directory of the users crontabs.
+    pub statedir: String,
+    /// Directory of the `cron.<schedule>` script directories.
+    pub confdir: String,
+    /// Directory of the `systemd-cron` helpers.
+    pub libdir: String,
+    /// `None` leaves it to the systemd version, see `persistence`.
+    pub persistent: Option<Persistence>,
+    /// `RandomizedDelaySec=` for `RANDOM_DELAY`, `AccuracySec=` otherwise,
+    /// `None` leaves it to the systemd version.
+    pub randomized_delay: Option<bool>,
+    /// Schedules whose script directory is run, among the ones with installed units.
+    pub schedules: Vec<String>,
+    /// Default of `SHELL`.
+    pub shell: String,
+    /// Default of `PATH`, systemd's own if unset.
+    pub path: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            statedir: build::USERS_CRONTAB_DIR.to_owned(),
+            confdir: build::CONF_DIR.to_owned(),
+            libdir: build::LIB_DIR.to_owned(),
+            persistent: None,
+            randomized_delay: None,
+            schedules: build::SCHEDULES.iter().map(|&s| s.to_owned()).collect(),
+            shell: "/bin/sh".to_owned(),
+            path: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match *self {
+            Value::Str(_) => "a string",
+            Value::Bool(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn new(content: &str) -> Parser {
+        Parser {
+            chars: content.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    // whitespace, newlines and comments, between entries and inside arrays
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_line(),
+                _ => break,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_blanks();
+        match self.peek() {
+            None => Ok(()),
+            Some('#') | Some('\n') | Some('\r') => {
+                self.skip_line();
+                Ok(())
+            }
+            Some(c) => Err(format!("unexpected {:?} after the value", c)),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                break;
+            }
+            key.push(c);
+            self.bump();
+        }
+        if key.is_empty() {
+            return Err("expected a key".to_owned());
+        }
+        Ok(key)
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            // the newline is left for the next line to be parsed
+            let c = match self.peek() {
+                None | Some('\n') => return Err("unterminated string".to_owned()),
+                Some(c) => c,
+            };
+            self.bump();
+            match c {
+                c if c == quote => return Ok(value),
+                // literal strings, in single quotes, have no escapes
+                '\\' if quote == '"' => match self.bump() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => return Err(format!("unsupported escape \\{}", c)),
+                    None => return Err("unterminated string".to_owned()),
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                self.bump();
+                self.string(quote).map(Value::Str)
+            }
+            Some('[') => {
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_trivia();
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_trivia();
+                    match self.bump() {
+                        Some(',') => (),
+                        Some(']') => return Ok(Value::Array(items)),
+                        _ => return Err("expected , or ] in array".to_owned()),
+                    }
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || ",]#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    self.bump();
+                }
+                match &*word {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "" => Err("expected a value".to_owned()),
+                    word => Err(format!("unsupported value {}, strings must be quoted", word)),
+                }
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<(String, Value), String> {
+        if self.peek() == Some('[') {
+            return Err("tables are not supported".to_owned());
+        }
+        let key = self.key()?;
+        self.skip_blanks();
+        if self.bump() != Some('=') {
+            return Err(format!("expected = after {}", key));
+        }
+        self.skip_blanks();
+        let value = self.value()?;
+        self.end_of_line()?;
+        Ok((key, value))
+    }
+}
+
+fn string_value(key: &str, value: Value, warnings: &mut Vec<String>) -> Option<String> {
+    match value {
+        Value::Str(value) => Some(value),
+        value => {
+            warnings.push(format!("{} must be a string, not {}", key, value.kind()));
+            None
+        }
+    }
+}
+
+fn dir_value(key: &str, value: Value, warnings: &mut Vec<String>) -> Option<String> {
+    let dir = string_value(key, value, warnings)?;
+    if !Path::new(&dir).is_absolute() {
+        warnings.push(format!("{} must be an absolute path, not {}", key, dir));
+        return None;
+    }
+    Some(dir)
+}
+
+impl Config {
+    /// Parse the settings, with the problems found in the content.
+    pub fn parse(content: &str) -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut warnings = Vec::new();
+        let mut parser = Parser::new(content);
+
+        loop {
+            parser.skip_trivia();
+            if parser.peek().is_none() {
+                break;
+            }
+
+            let line = parser.line;
+            let (key, value) = match parser.entry() {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warnings.push(format!("line {}: {}", line, err));
+                    parser.skip_line();
+                    continue;
+                }
+            };
+
+            let mut problems = Vec::new();
+            match &*key {
+                "statedir" => config.statedir = dir_value(&key, value, &mut problems).unwrap_or(config.statedir),
+                "confdir" => config.confdir = dir_value(&key, value, &mut problems).unwrap_or(config.confdir),
+                "libdir" => config.libdir = dir_value(&key, value, &mut problems).unwrap_or(config.libdir),
+                "shell" => config.shell = string_value(&key, value, &mut problems).unwrap_or(config.shell),
+                "path" => config.path = string_value(&key, value, &mut problems).or(config.path),
+                "persistent" => match value {
+                    Value::Bool(true) => config.persistent = Some(Persistence::Yes),
+                    Value::Bool(false) => config.persistent = Some(Persistence::No),
+                    Value::Str(ref value) if value == "auto" => config.persistent = Some(Persistence::Auto),
+                    _ => problems.push("persistent must be true, false or \"auto\"".to_owned()),
+                },
+                "randomized_delay" => match value {
+                    Value::Bool(enabled) => config.randomized_delay = Some(enabled),
+                    value => problems.push(format!("randomized_delay must be a boolean, not {}", value.kind())),
+                },
+                "schedules" => match value {
+                    Value::Array(items) => {
+                        config.schedules.clear();
+                        for item in items {
+                            match item {
+                                Value::Str(ref schedule) if build::SCHEDULES.contains(&&**schedule) => {
+                                    config.schedules.push(schedule.clone())
+                                }
+                                Value::Str(schedule) => problems.push(format!("no units are installed for the {} schedule", schedule)),
+                                item => problems.push(format!("schedules must be strings, not {}", item.kind())),
+                            }
+                        }
+                    }
+                    value => problems.push(format!("schedules must be an array, not {}", value.kind())),
+                },
+                _ => problems.push(format!("unknown option {}", key)),
+            }
+            warnings.extend(problems.into_iter().map(|problem| format!("line {}: {}", line, problem)));
+        }
+
+        (config, warnings)
+    }
+
+    /// Default of `PERSISTENT`: the setting, else auto when systemd supports persistent
+    /// timers, see `persistent_timers`.
+    pub fn persistence(&self, support: Option<Support>) -> Persistence {
+        self.persistent
+            .unwrap_or(if Config::persistent_timers(support) { Persistence::Auto } else { Persistence::No })
+    }
+
+    /// Whether timers may have `Persistent=`: whether systemd supports it, else the build
+    /// setting when the version is unknown.
+    pub fn persistent_timers(support: Option<Support>) -> bool {
+        support.map(|s| s.persistent()).unwrap_or(cfg!(feature = "persistent"))
+    }
+
+    /// Whether `RANDOM_DELAY` sets `RandomizedDelaySec=`: the setting, else whether systemd
+    /// supports it, else the build setting when the version is unknown.
+    pub fn randomized_delay(&self, support: Option<Support>) -> bool {
+        self.randomized_delay
+            .unwrap_or_else(|| support.map(|s| s.randomized_delay()).unwrap_or(cfg!(feature = "randomized-delay")))
+    }
+
+    /// Read the configuration file, the build settings are used if it doesn't exist.
+    pub fn load() -> (Config, Vec<String>) {
+        let mut content = String::new();
+        match File::open(path()).and_then(|mut file| file.read_to_string(&mut content)) {
+            Ok(_) => Config::parse(&content),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (Config::default(), Vec::new()),
+            Err(err) => (Config::default(), vec![format!("error reading file: {}", err)]),
+        }
+    }
+}
+
+/// Path of the configuration file, inside the root.
+pub fn path() -> PathBuf {
+    sysroot::path(Path::new(build::CONF_DIR).join(CONFIG_FILE))
+}
+
+/// Load the configuration of this process, returning the problems found in the file
+/// for the caller to log. Nothing is returned once it is loaded.
+pub fn init() -> Vec<String> {
+    let mut warnings = Vec::new();
+    CONFIG.get_or_init(|| {
+        let (config, problems) = Config::load();
+        warnings = problems;
+        config
+    });
+    warnings
+}
+
+/// The configuration of this process, loaded on first use.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, Config, Persistence};
+    use crate::systemd::Support;
+
+    #[test]
+    fn strings_and_escapes() {
+        let (config, warnings) = Config::parse(
+            r#"
+shell = "/bin/bash"
+path = "/usr/bin:\"quoted\"\\tab\t"
+libdir = '/usr/lib\no\escapes'
+"#,
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(config.shell, "/bin/bash");
+        assert_eq!(config.path.as_deref(), Some("/usr/bin:\"quoted\"\\tab\t"));
+        assert_eq!(config.libdir, r"/usr/lib\no\escapes");
+    }
+
+    #[test]
+    fn invalid_strings() {
+        let (config, warnings) = Config::parse("shell = \"/bin/sh\nshell = \"\\q\"\nshell = /bin/sh\n");
+        assert_eq!(config.shell, Config::default().shell);
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: unterminated string",
+                "line 2: unsupported escape \\q",
+                "line 3: unsupported value /bin/sh, strings must be quoted",
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_across_lines_and_comments() {
+        let schedules = build::SCHEDULES.iter().map(|s| format!("\"{}\"", s)).collect::<Vec<_>>();
+        let content = format!(
+            "# settings\nschedules = [  # enabled\n{}\n]\npersistent = \"auto\" # comment\nrandomized_delay = false\n",
+            schedules.iter().map(|s| format!("  {}, # schedule\n", s)).collect::<String>()
+        );
+        let (config, warnings) = Config::parse(&content);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(config.schedules, build::SCHEDULES);
+        assert_eq!(config.persistent, Some(Persistence::Auto));
+        assert_eq!(config.randomized_delay, Some(false));
+
+        let (config, warnings) = Config::parse("schedules = []");
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(config.schedules.is_empty());
+    }
+
+    #[test]
+    fn unknown_schedule() {
+        let (config, warnings) = Config::parse("schedules = [\"fortnightly\", true]");
+        assert!(config.schedules.is_empty());
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: no units are installed for the fortnightly schedule",
+                "line 1: schedules must be strings, not a boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_tables() {
+        let (config, warnings) = Config::parse("[cron]\nmailto = \"root\"\nshell = \"/bin/zsh\"\n");
+        assert_eq!(config.shell, "/bin/zsh");
+        assert_eq!(warnings, vec!["line 1: tables are not supported", "line 2: unknown option mailto"]);
+    }
+
+    #[test]
+    fn relative_dirs() {
+        let (config, warnings) = Config::parse("statedir = \"var/spool/cron\"\nconfdir = \"/srv/etc\"\n");
+        assert_eq!(config.statedir, Config::default().statedir);
+        assert_eq!(config.confdir, "/srv/etc");
+        assert_eq!(warnings, vec!["line 1: statedir must be an absolute path, not var/spool/cron"]);
+    }
+
+    #[test]
+    fn wrong_types() {
+        let (config, warnings) = Config::parse("persistent = \"yes\"\nrandomized_delay = \"no\"\nshell = [\"/bin/sh\"]\n");
+        assert_eq!(config.persistent, None);
+        assert_eq!(config.randomized_delay, None);
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: persistent must be true, false or \"auto\"",
+                "line 2: randomized_delay must be a boolean, not a string",
+                "line 3: shell must be a string, not an array",
+            ]
+        );
+    }
+
+    #[test]
+    fn persistence_fallback() {
+        let config = Config::default();
+        assert_eq!(config.persistence(Some(Support::new(211))), Persistence::No);
+        assert!(!Config::persistent_timers(Some(Support::new(211))));
+        assert_eq!(config.persistence(Some(Support::new(212))), Persistence::Auto);
+        assert!(Config::persistent_timers(Some(Support::new(212))));
+
+        // an unknown version falls back to the build setting, for both
+        let build = cfg!(feature = "persistent");
+        assert_eq!(Config::persistent_timers(None), build);
+        assert_eq!(config.persistence(None), if build { Persistence::Auto } else { Persistence::No });
+    }
+}
@@ -0,0 +1,806 @@
+//! Minimal D-Bus client, just enough to talk to systemd over the system bus.
+//!
+//! Only method calls are supported: the connection authenticates with the
+//! EXTERNAL mechanism, sends a call and waits for the matching reply,
+//! skipping signals and other messages in between.
+//!
+//! Only `unix:path=` bus addresses are supported, others such as
+//! `unix:abstract=` or `tcp:` are refused.
+
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+
+static SYSTEM_BUS_ADDRESS: &str = "unix:path=/run/dbus/system_bus_socket";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+// sanity limit for incoming messages, the spec allows up to 128MiB
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int16(i16),
+    UInt16(u16),
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    UInt64(u64),
+    Double(f64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    UnixFd(u32),
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref s) | Value::ObjectPath(ref s) | Value::Signature(ref s) => Some(&**s),
+            Value::Variant(ref v) => v.as_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Byte(v) => Some(i64::from(v)),
+            Value::Int16(v) => Some(i64::from(v)),
+            Value::UInt16(v) => Some(i64::from(v)),
+            Value::Int32(v) => Some(i64::from(v)),
+            Value::UInt32(v) => Some(i64::from(v)),
+            Value::Int64(v) => Some(v),
+            Value::UInt64(v) if v <= i64::MAX as u64 => Some(v as i64),
+            Value::Variant(ref v) => v.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::UInt64(v) => Some(v),
+            Value::Variant(ref v) => v.as_u64(),
+            _ => self.as_i64().and_then(|v| if v >= 0 { Some(v as u64) } else { None }),
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref items) => Some(&**items),
+            Value::Variant(ref v) => v.as_array(),
+            _ => None,
+        }
+    }
+
+    /// Bytes of an `ay` value, like systemd's InvocationID.
+    pub fn as_bytes(&self) -> Option<Vec<u8>> {
+        self.as_array().and_then(|items| {
+            items
+                .iter()
+                .map(|item| match *item {
+                    Value::Byte(b) => Some(b),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    fn signature(&self) -> String {
+        match *self {
+            Value::Byte(_) => "y".to_owned(),
+            Value::Bool(_) => "b".to_owned(),
+            Value::Int16(_) => "n".to_owned(),
+            Value::UInt16(_) => "q".to_owned(),
+            Value::Int32(_) => "i".to_owned(),
+            Value::UInt32(_) => "u".to_owned(),
+            Value::Int64(_) => "x".to_owned(),
+            Value::UInt64(_) => "t".to_owned(),
+            Value::Double(_) => "d".to_owned(),
+            Value::Str(_) => "s".to_owned(),
+            Value::ObjectPath(_) => "o".to_owned(),
+            Value::Signature(_) => "g".to_owned(),
+            Value::UnixFd(_) => "h".to_owned(),
+            // empty arrays are sent as arrays of strings
+            Value::Array(ref items) => format!("a{}", items.first().map(Value::signature).unwrap_or_else(|| "s".to_owned())),
+            Value::Struct(ref items) => format!("({})", items.iter().map(Value::signature).collect::<String>()),
+            Value::DictEntry(ref k, ref v) => format!("{{{}{}}}", k.signature(), v.signature()),
+            Value::Variant(_) => "v".to_owned(),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Byte(v) => v.fmt(f),
+            Value::Bool(v) => v.fmt(f),
+            Value::Int16(v) => v.fmt(f),
+            Value::UInt16(v) => v.fmt(f),
+            Value::Int32(v) => v.fmt(f),
+            Value::UInt32(v) | Value::UnixFd(v) => v.fmt(f),
+            Value::Int64(v) => v.fmt(f),
+            Value::UInt64(v) => v.fmt(f),
+            Value::Double(v) => v.fmt(f),
+            Value::Str(ref v) | Value::ObjectPath(ref v) | Value::Signature(ref v) => v.fmt(f),
+            Value::Array(ref items) | Value::Struct(ref items) => {
+                let mut first = true;
+                for item in items {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    first = false;
+                    item.fmt(f)?;
+                }
+                Ok(())
+            }
+            Value::DictEntry(ref k, ref v) => write!(f, "{}={}", k, v),
+            Value::Variant(ref v) => v.fmt(f),
+        }
+    }
+}
+
+/// Error returned by the remote side of a method call.
+#[derive(Debug)]
+pub struct MethodError {
+    pub name: String,
+    pub message: String,
+}
+
+impl Display for MethodError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl ::std::error::Error for MethodError {}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn align(&mut self, n: usize) {
+        let len = self.buf.len().div_ceil(n) * n;
+        self.buf.resize(len, 0);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match *value {
+            Value::Byte(v) => self.buf.push(v),
+            Value::Bool(v) => self.u32(v as u32),
+            Value::Int16(v) => {
+                self.align(2);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::UInt16(v) => {
+                self.align(2);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Int32(v) => {
+                self.align(4);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::UInt32(v) | Value::UnixFd(v) => self.u32(v),
+            Value::Int64(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::UInt64(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Double(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+            Value::Str(ref s) | Value::ObjectPath(ref s) => self.string(s),
+            Value::Signature(ref s) => self.signature(s),
+            Value::Array(ref items) => {
+                self.u32(0);
+                let len_pos = self.buf.len() - 4;
+                // padding to the element alignment is not part of the array length
+                self.align(signature_alignment(value.signature().as_bytes()[1]));
+                let start = self.buf.len();
+                for item in items {
+                    self.value(item);
+                }
+                let len = (self.buf.len() - start) as u32;
+                self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+            }
+            Value::Struct(ref items) => {
+                self.align(8);
+                for item in items {
+                    self.value(item);
+                }
+            }
+            Value::DictEntry(ref k, ref v) => {
+                self.align(8);
+                self.value(k);
+                self.value(v);
+            }
+            Value::Variant(ref v) => {
+                self.signature(&*v.signature());
+                self.value(v);
+            }
+        }
+    }
+}
+
+fn signature_alignment(code: u8) -> usize {
+    match code {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+// offset of the type that ends a complete type starting at `sig[0]`
+fn single_type_len(sig: &[u8]) -> io::Result<usize> {
+    match sig.first() {
+        None => Err(invalid_data("empty signature")),
+        Some(b'a') => single_type_len(&sig[1..]).map(|n| n + 1),
+        Some(&open @ b'(') | Some(&open @ b'{') => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut pos = 1;
+            while sig.get(pos) != Some(&close) {
+                if pos >= sig.len() {
+                    return Err(invalid_data("unbalanced signature"));
+                }
+                pos += single_type_len(&sig[pos..])?;
+            }
+            Ok(pos + 1)
+        }
+        Some(_) => Ok(1),
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.div_ceil(n) * n;
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(invalid_data("truncated message"));
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.align(N);
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.fixed::<4>().map(u32::from_le_bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| invalid_data("invalid UTF-8 string"))
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let len = self.take(1)?[0] as usize;
+        let bytes = self.take(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| invalid_data("invalid signature"))
+    }
+
+    fn values(&mut self, sig: &[u8]) -> io::Result<Vec<Value>> {
+        let mut values = Vec::new();
+        let mut pos = 0;
+        while pos < sig.len() {
+            let len = single_type_len(&sig[pos..])?;
+            values.push(self.value(&sig[pos..pos + len])?);
+            pos += len;
+        }
+        Ok(values)
+    }
+
+    fn value(&mut self, sig: &[u8]) -> io::Result<Value> {
+        Ok(match sig[0] {
+            b'y' => Value::Byte(self.take(1)?[0]),
+            b'b' => Value::Bool(self.u32()? != 0),
+            b'n' => Value::Int16(self.fixed::<2>().map(i16::from_le_bytes)?),
+            b'q' => Value::UInt16(self.fixed::<2>().map(u16::from_le_bytes)?),
+            b'i' => Value::Int32(self.fixed::<4>().map(i32::from_le_bytes)?),
+            b'u' => Value::UInt32(self.u32()?),
+            b'h' => Value::UnixFd(self.u32()?),
+            b'x' => Value::Int64(self.fixed::<8>().map(i64::from_le_bytes)?),
+            b't' => Value::UInt64(self.fixed::<8>().map(u64::from_le_bytes)?),
+            b'd' => Value::Double(f64::from_bits(self.fixed::<8>().map(u64::from_le_bytes)?)),
+            b's' => Value::Str(self.string()?),
+            b'o' => Value::ObjectPath(self.string()?),
+            b'g' => Value::Signature(self.signature()?),
+            b'v' => {
+                let inner = self.signature()?;
+                if single_type_len(inner.as_bytes())? != inner.len() {
+                    return Err(invalid_data("invalid variant signature"));
+                }
+                Value::Variant(Box::new(self.value(inner.as_bytes())?))
+            }
+            b'a' => {
+                let len = self.u32()? as usize;
+                let item = &sig[1..1 + single_type_len(&sig[1..])?];
+                self.align(signature_alignment(item[0]));
+                let end = self.pos + len;
+                if end > self.buf.len() {
+                    return Err(invalid_data("truncated array"));
+                }
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.value(item)?);
+                }
+                Value::Array(items)
+            }
+            b'(' => {
+                self.align(8);
+                Value::Struct(self.values(&sig[1..sig.len() - 1])?)
+            }
+            b'{' => {
+                self.align(8);
+                let mut entry = self.values(&sig[1..sig.len() - 1])?;
+                if entry.len() != 2 {
+                    return Err(invalid_data("invalid dict entry"));
+                }
+                let value = entry.pop().unwrap();
+                let key = entry.pop().unwrap();
+                Value::DictEntry(Box::new(key), Box::new(value))
+            }
+            _ => return Err(invalid_data("unsupported type in signature")),
+        })
+    }
+}
+
+struct Message {
+    kind: u8,
+    // only the mock service of the tests answers calls
+    #[cfg_attr(not(test), allow(dead_code))]
+    serial: u32,
+    #[cfg_attr(not(test), allow(dead_code))]
+    sender: Option<String>,
+    #[cfg_attr(not(test), allow(dead_code))]
+    member: Option<String>,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<Value>,
+}
+
+fn header_field(code: u8, value: Value) -> Value {
+    Value::Struct(vec![Value::Byte(code), Value::Variant(Box::new(value))])
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connect to the system bus, honouring `DBUS_SYSTEM_BUS_ADDRESS`.
+    pub fn system() -> io::Result<Connection> {
+        let address = env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_else(|_| SYSTEM_BUS_ADDRESS.to_owned());
+        Connection::open(&*address)
+    }
+
+    /// Connect to the first `unix:path=...` of a bus address, fails on any other transport.
+    pub fn open(address: &str) -> io::Result<Connection> {
+        let path = address
+            .split(';')
+            .filter_map(|addr| addr.strip_prefix("unix:"))
+            .flat_map(|params| params.split(','))
+            .filter_map(|param| param.strip_prefix("path="))
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported bus address {}, only unix:path= is supported", address))
+            })?;
+
+        let mut conn = Connection {
+            stream: UnixStream::connect(path)?,
+            serial: 0,
+        };
+        conn.authenticate()?;
+        conn.call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "Hello", &[])?;
+        Ok(conn)
+    }
+
+    fn authenticate(&mut self) -> io::Result<()> {
+        let uid = unsafe { libc::geteuid() }.to_string();
+        let hex_uid = uid.bytes().map(|b| format!("{:02x}", b)).collect::<String>();
+        self.stream.write_all(b"\0")?;
+        self.stream.write_all(format!("AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+
+        // read the reply byte by byte, the binary protocol starts right after it
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while !line.ends_with(b"\r\n") {
+            if self.stream.read(&mut byte)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bus closed connection during authentication"));
+            }
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "bus authentication rejected"));
+        }
+
+        self.stream.write_all(b"BEGIN\r\n")
+    }
+
+    /// Call a method and wait for its reply, remote errors are returned as `MethodError`.
+    pub fn call(&mut self, destination: &str, path: &str, interface: &str, member: &str, args: &[Value]) -> io::Result<Vec<Value>> {
+        let fields = vec![
+            header_field(FIELD_PATH, Value::ObjectPath(path.to_owned())),
+            header_field(FIELD_INTERFACE, Value::Str(interface.to_owned())),
+            header_field(FIELD_MEMBER, Value::Str(member.to_owned())),
+            header_field(FIELD_DESTINATION, Value::Str(destination.to_owned())),
+        ];
+        let serial = self.send(METHOD_CALL, fields, args)?;
+
+        loop {
+            let reply = self.receive()?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            return match reply.kind {
+                METHOD_RETURN => Ok(reply.body),
+                ERROR => Err(io::Error::other(MethodError {
+                    name: reply.error_name.unwrap_or_default(),
+                    message: reply.body.first().and_then(Value::as_str).unwrap_or("").to_owned(),
+                })),
+                _ => Err(invalid_data("unexpected reply type")),
+            };
+        }
+    }
+
+    fn send(&mut self, kind: u8, mut fields: Vec<Value>, args: &[Value]) -> io::Result<u32> {
+        self.serial += 1;
+
+        let signature = args.iter().map(Value::signature).collect::<String>();
+        let mut body = Encoder { buf: Vec::new() };
+        for arg in args {
+            body.value(arg);
+        }
+        if !signature.is_empty() {
+            fields.push(header_field(FIELD_SIGNATURE, Value::Signature(signature)));
+        }
+
+        let mut msg = Encoder {
+            buf: vec![b'l', kind, 0, 1],
+        };
+        msg.u32(body.buf.len() as u32);
+        msg.u32(self.serial);
+        msg.value(&Value::Array(fields));
+        msg.align(8);
+        msg.buf.extend_from_slice(&*body.buf);
+        self.stream.write_all(&*msg.buf)?;
+        Ok(self.serial)
+    }
+
+    fn receive(&mut self) -> io::Result<Message> {
+        let mut fixed = [0u8; 16];
+        self.stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(invalid_data("big endian messages are not supported"));
+        }
+
+        let body_len = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]) as usize;
+        let fields_len = u32::from_le_bytes([fixed[12], fixed[13], fixed[14], fixed[15]]) as usize;
+        let header_len = (16 + fields_len).div_ceil(8) * 8;
+        if header_len + body_len > MAX_MESSAGE_SIZE {
+            return Err(invalid_data("message too large"));
+        }
+
+        let mut buf = fixed.to_vec();
+        buf.resize(header_len + body_len, 0);
+        self.stream.read_exact(&mut buf[16..])?;
+
+        let mut decoder = Decoder { buf: &*buf, pos: 12 };
+        let fields = decoder.value(b"a(yv)")?;
+
+        let mut message = Message {
+            kind: fixed[1],
+            serial: u32::from_le_bytes([fixed[8], fixed[9], fixed[10], fixed[11]]),
+            sender: None,
+            member: None,
+            reply_serial: None,
+            error_name: None,
+            body: Vec::new(),
+        };
+        let mut signature = String::new();
+        for field in fields.as_array().unwrap_or(&[]) {
+            if let Value::Struct(ref parts) = *field {
+                match (parts.first(), parts.get(1)) {
+                    (Some(&Value::Byte(FIELD_REPLY_SERIAL)), Some(value)) => message.reply_serial = value.as_i64().map(|v| v as u32),
+                    (Some(&Value::Byte(FIELD_ERROR_NAME)), Some(value)) => message.error_name = value.as_str().map(ToOwned::to_owned),
+                    (Some(&Value::Byte(FIELD_SENDER)), Some(value)) => message.sender = value.as_str().map(ToOwned::to_owned),
+                    (Some(&Value::Byte(FIELD_MEMBER)), Some(value)) => message.member = value.as_str().map(ToOwned::to_owned),
+                    (Some(&Value::Byte(FIELD_SIGNATURE)), Some(value)) => signature = value.as_str().unwrap_or("").to_owned(),
+                    _ => (),
+                }
+            }
+        }
+
+        let mut decoder = Decoder {
+            buf: &*buf,
+            pos: header_len,
+        };
+        message.body = decoder.values(signature.as_bytes())?;
+        Ok(message)
+    }
+}
+
+/// A private bus and mock services, to test the clients without systemd.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{
+        header_field, Connection, Value, ERROR, FIELD_DESTINATION, FIELD_ERROR_NAME, FIELD_REPLY_SERIAL, METHOD_CALL, METHOD_RETURN,
+    };
+    use std::env;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader};
+    use std::path::PathBuf;
+    use std::process::{self, Child, Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    static BUSES: AtomicUsize = AtomicUsize::new(0);
+
+    static CONFIG: &str = "<busconfig>
+  <type>session</type>
+  <listen>unix:path={dir}/bus</listen>
+  <auth>EXTERNAL</auth>
+  <policy context=\"default\">
+    <allow send_destination=\"*\"/>
+    <allow receive_sender=\"*\"/>
+    <allow own=\"*\"/>
+  </policy>
+</busconfig>
+";
+
+    /// A dbus-daemon listening in a temporary directory, killed on drop.
+    pub struct PrivateBus {
+        daemon: Child,
+        dir: PathBuf,
+        pub address: String,
+    }
+
+    impl PrivateBus {
+        pub fn start() -> io::Result<PrivateBus> {
+            let dir = env::temp_dir().join(format!("dbus-test.{}.{}", process::id(), BUSES.fetch_add(1, Ordering::SeqCst)));
+            fs::create_dir_all(&dir)?;
+            let config = dir.join("bus.conf");
+            fs::write(&config, CONFIG.replace("{dir}", &*dir.to_string_lossy()))?;
+
+            let mut daemon = Command::new("dbus-daemon")
+                .arg("--nofork")
+                .arg("--print-address")
+                .arg(format!("--config-file={}", config.display()))
+                .stdout(Stdio::piped())
+                .spawn()?;
+
+            // printed once the daemon listens
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address)?;
+            let bus = PrivateBus {
+                daemon,
+                dir,
+                address: address.trim().to_owned(),
+            };
+            if bus.address.is_empty() {
+                return Err(io::Error::other("dbus-daemon didn't start"));
+            }
+            Ok(bus)
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Own `name` on the bus and answer its method calls with `handler`, given the member
+    /// and arguments of each call, until the bus goes away. Errors are D-Bus error names.
+    pub fn serve<F>(address: &str, name: &str, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(&str, &[Value]) -> Result<Vec<Value>, String> + Send + 'static,
+    {
+        let mut conn = Connection::open(address)?;
+        conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+            &[Value::Str(name.to_owned()), Value::UInt32(0)],
+        )?;
+
+        thread::spawn(move || {
+            while let Ok(call) = conn.receive() {
+                if call.kind != METHOD_CALL {
+                    continue;
+                }
+                let mut fields = vec![
+                    header_field(FIELD_REPLY_SERIAL, Value::UInt32(call.serial)),
+                    header_field(FIELD_DESTINATION, Value::Str(call.sender.unwrap_or_default())),
+                ];
+                let sent = match handler(&*call.member.unwrap_or_default(), &*call.body) {
+                    Ok(values) => conn.send(METHOD_RETURN, fields, &*values),
+                    Err(error_name) => {
+                        fields.push(header_field(FIELD_ERROR_NAME, Value::Str(error_name)));
+                        conn.send(ERROR, fields, &[Value::Str("refused by the mock service".to_owned())])
+                    }
+                };
+                if sent.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{serve, PrivateBus};
+    use super::{Connection, Decoder, Encoder, MethodError, Value};
+    use std::io;
+
+    fn encode(values: &[Value]) -> Vec<u8> {
+        let mut encoder = Encoder { buf: Vec::new() };
+        for value in values {
+            encoder.value(value);
+        }
+        encoder.buf
+    }
+
+    fn decode(buf: &[u8], sig: &str) -> Vec<Value> {
+        Decoder { buf, pos: 0 }.values(sig.as_bytes()).unwrap()
+    }
+
+    fn round_trip(values: Vec<Value>) {
+        let sig = values.iter().map(Value::signature).collect::<String>();
+        assert_eq!(decode(&encode(&values), &sig), values);
+    }
+
+    #[test]
+    fn basic_types() {
+        round_trip(vec![
+            Value::Byte(7),
+            Value::Bool(true),
+            Value::Int16(-2),
+            Value::UInt16(3),
+            Value::Int32(-4),
+            Value::UInt32(5),
+            Value::Int64(-6),
+            Value::UInt64(u64::MAX),
+            Value::Double(1.5),
+            Value::Str("cron.target".to_owned()),
+            Value::ObjectPath("/org/freedesktop/systemd1".to_owned()),
+            Value::Signature("a{sv}".to_owned()),
+            Value::UnixFd(1),
+        ]);
+    }
+
+    #[test]
+    fn containers() {
+        round_trip(vec![
+            Value::Byte(1),
+            Value::Array(vec![Value::UInt64(1), Value::UInt64(2)]),
+            Value::Struct(vec![Value::Byte(2), Value::Str("x".to_owned())]),
+            Value::Array(vec![Value::DictEntry(
+                Box::new(Value::Str("InvocationID".to_owned())),
+                Box::new(Value::Variant(Box::new(Value::Array(vec![Value::Byte(0xab), Value::Byte(0xcd)])))),
+            )]),
+            Value::Array(vec![]),
+        ]);
+    }
+
+    #[test]
+    fn wire_format() {
+        assert_eq!(encode(&[Value::Str("ab".to_owned())]), b"\x02\0\0\0ab\0");
+        // the padding before the first element is not part of the array length
+        assert_eq!(encode(&[Value::Array(vec![Value::UInt64(1)])]), [8, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode(&[Value::Variant(Box::new(Value::Bool(true)))]), b"\x01b\0\0\x01\0\0\0");
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let buf = encode(&[Value::Str("truncated".to_owned())]);
+        assert!(Decoder { buf: &buf[..6], pos: 0 }.values(b"s").is_err());
+        assert!(Decoder { buf: &buf, pos: 0 }.values(b"(s").is_err());
+        assert!(Decoder { buf: b"\x02ss\0", pos: 0 }.values(b"v").is_err());
+    }
+
+    #[test]
+    fn invocation_id_bytes() {
+        let id = Value::Variant(Box::new(Value::Array(vec![Value::Byte(1), Value::Byte(0xff)])));
+        assert_eq!(id.as_bytes(), Some(vec![1, 0xff]));
+        assert_eq!(Value::Str("x".to_owned()).as_bytes(), None);
+    }
+
+    #[test]
+    fn unsupported_addresses() {
+        for address in &["unix:abstract=/tmp/dbus-x", "tcp:host=localhost,port=1234", ""] {
+            let err = Connection::open(address).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    // needs dbus-daemon, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn calls_through_a_bus() {
+        let bus = PrivateBus::start().unwrap();
+        serve(&*bus.address, "org.example.Echo", |member, args| match member {
+            "Echo" => Ok(args.to_vec()),
+            _ => Err("org.example.Error.Unknown".to_owned()),
+        })
+        .unwrap();
+
+        let mut conn = Connection::open(&*bus.address).unwrap();
+        let args = vec![
+            Value::Str("cron.target".to_owned()),
+            Value::Array(vec![Value::Byte(1), Value::Byte(2)]),
+            Value::UInt64(7),
+        ];
+        assert_eq!(conn.call("org.example.Echo", "/", "org.example.Echo", "Echo", &*args).unwrap(), args);
+
+        let err = conn.call("org.example.Echo", "/", "org.example.Echo", "Fail", &[]).unwrap_err();
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<MethodError>()).unwrap();
+        assert_eq!(err.name, "org.example.Error.Unknown");
+
+        // the bus itself answers for names nobody owns
+        assert!(conn.call("org.example.Nobody", "/", "org.example.Nobody", "Echo", &[]).is_err());
+    }
+}
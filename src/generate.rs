@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
 use std::fs::{create_dir_all, metadata, set_permissions, File};
 use std::io::{self, Write};
 use std::os::unix::ffi::OsStrExt;
@@ -6,40 +6,57 @@ use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
 use std::path::Path;
 
 use cronparse::crontab::{CrontabEntry, SystemCrontabEntry, UserCrontabEntry};
-use cronparse::interval::Interval;
-use cronparse::schedule::{Calendar, Period, Schedule};
-use cronparse::Limited;
+use cronparse::schedule::{Period, Schedule};
 
-use pgs_files::passwd::{get_entry_by_name, get_entry_by_uid};
+use systemd_crontab_generator::calendar::expand_shorthand;
+use systemd_crontab_generator::config::{self, Config, Persistence};
+use systemd_crontab_generator::identity;
+use systemd_crontab_generator::schedule::{entry_event, Options};
+use systemd_crontab_generator::sysroot;
+use systemd_crontab_generator::systemd::Support;
 
-use super::{LIB_DIR, PACKAGE, REBOOT_FILE};
+use super::{timer_generated, PACKAGE, REBOOT_FILE};
+use crate::checks::account_status;
+use crate::cmdline;
 
+/// Errors are failures to write the units, jobs which can't run are skipped with a warning.
 pub fn generate_systemd_units(entry: CrontabEntry, env: &BTreeMap<String, String>, path: &Path, dstdir: &Path) -> io::Result<()> {
     use cronparse::crontab::CrontabEntry::*;
 
     info!("generating units for {}: \"{}\", {:?}", path.display(), entry, env);
 
-    let owner = metadata(path)?.uid();
+    let owner = match metadata(path) {
+        Ok(meta) => meta.uid(),
+        Err(err) => {
+            warn!("skipping job from {}: {}", path.display(), err);
+            return Ok(());
+        }
+    };
+    // the units name the files the way the system sees them
+    let source_path = sysroot::strip(path);
+    let config = config::get();
+    let support = Support::installed();
 
-    let mut persistent = env
+    let mut persistent = match env
         .get("PERSISTENT")
-        .and_then(|v| match &**v {
-            "yes" | "true" | "1" => Some(true),
-            "auto" | "" => None,
-            _ => Some(false),
-        })
-        .unwrap_or_else(|| match entry {
+        .map(|v| Persistence::from_value(v))
+        .unwrap_or_else(|| config.persistence(support))
+    {
+        Persistence::Yes => true,
+        Persistence::No => false,
+        Persistence::Auto => matches!(
+            entry,
             Anacron(_)
-            | User(UserCrontabEntry {
-                sched: Schedule::Period(_),
-                ..
-            })
-            | System(SystemCrontabEntry {
-                sched: Schedule::Period(_),
-                ..
-            }) => true,
-            _ => false,
-        });
+                | User(UserCrontabEntry {
+                    sched: Schedule::Period(_),
+                    ..
+                })
+                | System(SystemCrontabEntry {
+                    sched: Schedule::Period(_),
+                    ..
+                })
+        ),
+    };
 
     let batch = env
         .get("BATCH")
@@ -50,117 +67,31 @@ pub fn generate_systemd_units(entry: CrontabEntry, env: &BTreeMap<String, String
         .unwrap_or(false);
 
     let random_delay = env.get("RANDOM_DELAY").and_then(|v| v.parse::<u64>().ok()).unwrap_or(1);
-    let mut delay = env.get("DELAY").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
-    let hour = env
-        .get("START_HOURS_RANGE")
-        .and_then(|v| v.splitn(1, '-').next().and_then(|v| v.parse::<u64>().ok()))
-        .unwrap_or(0);
-    let shell = env.get("SHELL").map(|v| &**v).unwrap_or("/bin/sh");
-    let daemon_reload = metadata(REBOOT_FILE).map(|m| m.is_file()).unwrap_or(false);
-    let timezone = env.get("CRON_TZ").map(|v| &**v).unwrap_or("");
-
-    let schedule = entry
-        .period()
-        .and_then(|period| {
-            match *period {
-                Period::Reboot => {
-                    persistent = false;
-                    if delay == 0 {
-                        delay = 1;
-                    }
-                    None
-                }
-                Period::Minutely => {
-                    persistent = false;
-                    Some("minutely".to_owned())
-                }
-                Period::Hourly => {
-                    if delay == 0 {
-                        Some("hourly".to_owned())
-                    } else {
-                        Some(format!("*-*-* *:{}:0 {}", delay, timezone))
-                    }
-                }
-                Period::Midnight => {
-                    if delay == 0 {
-                        Some("daily".to_owned())
-                    } else {
-                        Some(format!("*-*-* 0:{}:0 {}", delay, timezone))
-                    }
-                }
-                Period::Daily => {
-                    if delay == 0 && hour == 0 {
-                        Some("daily".to_owned())
-                    } else {
-                        Some(format!("*-*-* {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Weekly => {
-                    if delay == 0 && hour == 0 {
-                        Some("weekly".to_owned())
-                    } else {
-                        Some(format!("Mon *-*-* {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Monthly => {
-                    if delay == 0 && hour == 0 {
-                        Some("monthly".to_owned())
-                    } else {
-                        Some(format!("*-*-1 {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Quaterly => {
-                    if delay == 0 && hour == 0 {
-                        Some("quaterly".to_owned())
-                    } else {
-                        Some(format!("*-1,4,7,10-1 {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Biannually => {
-                    if delay == 0 && hour == 0 {
-                        Some("semiannually".to_owned())
-                    } else {
-                        Some(format!("*-1,7-1 {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Yearly => {
-                    if delay == 0 && hour == 0 {
-                        Some("yearly".to_owned())
-                    } else {
-                        Some(format!("*-1-1 {}:{}:0 {}", hour, delay, timezone))
-                    }
-                }
-                Period::Days(days) => {
-                    // workaround for anacrontab
-                    if days > 31 {
-                        Some(format!("*-1/{}-1 {}:{}:0 {}", days / 30, hour, delay, timezone))
-                    } else {
-                        Some(format!("*-*-1/{} {}:{}:0 {}", days, hour, delay, timezone))
-                    }
-                }
+    let Options { mut delay, hour, timezone } = Options::from_env(env);
+    let shell = env.get("SHELL").unwrap_or(&config.shell);
+    // an image is generated for its first boot, never reloaded
+    let daemon_reload = !sysroot::is_set() && metadata(REBOOT_FILE).map(|m| m.is_file()).unwrap_or(false);
+
+    match entry.period() {
+        Some(&Period::Reboot) => {
+            if !cmdline::get().reboot {
+                info!("skipping job from {}: @reboot jobs are disabled on the kernel command line", path.display());
+                return Ok(());
             }
-        })
-        .or_else(|| {
-            entry.calendar().and_then(|cal| {
-                let Calendar {
-                    ref dows,
-                    ref days,
-                    ref mons,
-                    ref hrs,
-                    ref mins,
-                } = *cal;
-
-                Some(format!(
-                    "{} *-{}-{} {}:{}:00 {}",
-                    linearize(&**dows, "", ToString::to_string),
-                    linearize(&**mons, "*", |&mon| (mon as u8).to_string()),
-                    linearize(&**days, "*", ToString::to_string),
-                    linearize(&**hrs, "*", ToString::to_string),
-                    linearize(&**mins, "*", ToString::to_string),
-                    timezone,
-                ))
-            })
-        });
+            persistent = false;
+            if delay == 0 {
+                delay = 1;
+            }
+        }
+        Some(&Period::Minutely) => persistent = false,
+        _ => (),
+    }
+
+    // the explicit form of the shorthands older systemd versions don't know
+    let schedule = entry_event(&entry, &Options { delay, hour, timezone }).map(|event| match expand_shorthand(&event) {
+        Some(explicit) if !support.map(|s| s.calendar_shorthand(&event)).unwrap_or(true) => explicit.to_owned(),
+        _ => event,
+    });
 
     if daemon_reload && schedule.is_none() {
         warn!("skipping job from {}: \"{}\"", path.display(), entry);
@@ -168,16 +99,41 @@ pub fn generate_systemd_units(entry: CrontabEntry, env: &BTreeMap<String, String
     }
 
     if let Some(cmd) = entry.command() {
-        // make sure we know the user
-        let user = entry
-            .user()
-            .and_then(get_entry_by_name)
-            .or_else(|| get_entry_by_uid(owner))
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown user"))?;
+        // system jobs name their user and never fall back to the owner of the file, root,
+        // users crontabs are named after their user
+        let user_name = match entry {
+            System(_) => entry.user().map(ToOwned::to_owned),
+            User(_) => path.file_name().and_then(|n| n.to_str()).map(ToOwned::to_owned),
+            _ => None,
+        };
+        // the accounts of users crontabs are checked with the rest of the file
+        if let (System(_), Some(status)) = (&entry, user_name.as_ref().and_then(|name| account_status(name))) {
+            warn!("skipping job from {}: account of {} is {}", path.display(), user_name.unwrap_or_default(), status);
+            return Ok(());
+        }
+
+        // directory services may not be up yet, the unit then names the user
+        // and systemd looks it up when the job starts
+        let user = match user_name {
+            Some(ref name) => identity::user_by_name(name),
+            None => identity::user_by_uid(owner),
+        };
+        let user_name = match (user_name, &user) {
+            (_, Some(user)) => user.name().to_owned(),
+            (Some(name), None) => {
+                info!("user {} of {} is unknown yet, it is looked up when the job starts", name, path.display());
+                name
+            }
+            (None, None) => {
+                warn!("skipping job from {}: unknown owner {}", path.display(), owner);
+                return Ok(());
+            }
+        };
+        let is_root = user.as_ref().map(|user| user.uid() == 0).unwrap_or(false);
 
         // generate unique cron job id
         let mut md5ctx = ::md5::Context::new();
-        md5ctx.consume(path.as_os_str().as_bytes());
+        md5ctx.consume(source_path.as_os_str().as_bytes());
         if let Some(ref schedule) = schedule {
             md5ctx.consume(schedule.as_bytes());
         }
@@ -197,7 +153,7 @@ pub fn generate_systemd_units(entry: CrontabEntry, env: &BTreeMap<String, String
         create_dir_all(&cron_target_wants_path)?;
 
         // process command in case it should be put into script
-        let command = if metadata(cmd).map(|m| m.is_file()).unwrap_or(false) {
+        let command = if metadata(sysroot::path(cmd)).map(|m| m.is_file()).unwrap_or(false) {
             cmd.to_owned()
         } else {
             let script_command_path = dstdir.join(format!("cron-{}.sh", md5hex));
@@ -212,7 +168,7 @@ pub fn generate_systemd_units(entry: CrontabEntry, env: &BTreeMap<String, String
             let mut perms = metadata(&script_command_path)?.permissions();
             perms.set_mode(0o755);
             set_permissions(&script_command_path, perms)?;
-            script_command_path.to_str().unwrap().to_owned()
+            sysroot::strip(&script_command_path).to_str().unwrap().to_owned()
         };
 
         debug!("generating service {:?} from {:?}", service_unit_path, path);
@@ -228,17 +184,27 @@ RefuseManualStart=true
 RefuseManualStop=true
 SourcePath={source_crontab_path}"###,
                 entry = entry,
-                source_crontab_path = path.display(),
+                source_crontab_path = source_path.display(),
             )?;
 
-            if env.contains_key("MAILTO") {
+            if env.contains_key("MAILTO") || env.contains_key("NOTIFY") {
                 writeln!(service_unit_file, "OnFailure=cron-failure@%i.service")?;
             }
 
-            if user.uid != 0 {
+            if !is_root {
                 writeln!(service_unit_file, "Requires=systemd-user-sessions.service")?;
-                if !user.dir.is_empty() {
-                    writeln!(service_unit_file, "RequiresMountsFor={}", user.dir)?;
+            }
+            match user {
+                Some(ref user) if !is_root => match identity::home_dir(user) {
+                    // only mounted while the user is logged in, skip the job otherwise
+                    Some(home) if identity::is_homed(user) => writeln!(service_unit_file, "ConditionPathIsDirectory={}", home.display())?,
+                    Some(home) => writeln!(service_unit_file, "RequiresMountsFor={}", home.display())?,
+                    None => (),
+                },
+                Some(_) => (),
+                None => {
+                    writeln!(service_unit_file, "Wants=nss-user-lookup.target")?;
+                    writeln!(service_unit_file, "After=nss-user-lookup.target")?;
                 }
             }
 
@@ -253,12 +219,13 @@ ExecStart={command}"###,
             )?;
 
             if schedule.is_some() && delay > 0 {
-                writeln!(service_unit_file, "ExecStartPre=-{}/{}/boot-delay {}", LIB_DIR, PACKAGE, delay)?;
+                writeln!(service_unit_file, "ExecStartPre=-{}/{}/boot-delay {}", config.libdir, PACKAGE, delay)?;
             }
 
-            if user.uid != 0 {
-                writeln!(service_unit_file, "User={}", user.name)?;
-                writeln!(service_unit_file, "WorkingDirectory=~")?;
+            if !is_root {
+                writeln!(service_unit_file, "User={}", user_name)?;
+                // the home directory of users looked up late may not be mounted
+                writeln!(service_unit_file, "WorkingDirectory={}", if user.is_some() { "~" } else { "-~" })?;
             }
 
             if let Some(group) = entry.group() {
@@ -274,6 +241,9 @@ ExecStart={command}"###,
                     writeln!(service_unit_file, r#"Environment="{}={}""#, name, value)?;
                 }
             }
+            if let (Some(ref path), false) = (&config.path, env.contains_key("PATH")) {
+                writeln!(service_unit_file, r#"Environment="PATH={}""#, path)?;
+            }
         }
 
         debug!("generating timer {:?} from {:?}", timer_unit_path, path);
@@ -293,12 +263,12 @@ SourcePath={source_crontab_path}
 [Timer]
 Unit={service_unit_name}"###,
                 entry = entry,
-                source_crontab_path = path.display(),
+                source_crontab_path = source_path.display(),
                 service_unit_name = service_unit_name,
             )?;
 
-            if cfg![feature = "persistent"] {
-                writeln!(timer_unit_file, "Persistent={}", persistent)?;
+            if persistent && Config::persistent_timers(support) {
+                writeln!(timer_unit_file, "Persistent=true")?;
             }
 
             if let Some(schedule) = schedule {
@@ -308,37 +278,20 @@ Unit={service_unit_name}"###,
             }
 
             if random_delay != 1 {
-                if cfg!(feature = "randomized-delay") {
+                if config.randomized_delay(support) {
                     writeln!(timer_unit_file, "RandomizedDelaySec={}m", random_delay)?;
                 } else {
                     writeln!(timer_unit_file, "AccuracySec={}m", random_delay)?;
                 }
             }
         }
-        symlink(timer_unit_path, cron_target_wants_path.join(timer_unit_name))?;
+        symlink(sysroot::strip(&timer_unit_path), cron_target_wants_path.join(&timer_unit_name))?;
+        timer_generated(&timer_unit_name);
     }
 
     Ok(())
 }
 
-fn linearize<T, C>(input: &[Interval<T>], star: &str, conv: C) -> String
-where
-    T: Limited,
-    C: Fn(&T) -> String,
-{
-    if input.len() == 1 && input[0] == Interval::Full(1) {
-        star.to_owned()
-    } else {
-        let mut output = String::new();
-        for part in input.iter().flat_map(|v| v.iter()).collect::<BTreeSet<_>>().iter() {
-            output.push_str(&*conv(&part));
-            output.push(',');
-        }
-        output.pop();
-        output
-    }
-}
-
 fn tohex(input: &[u8]) -> String {
     #[inline]
     fn hex(d: u8) -> char {
@@ -0,0 +1,135 @@
+//! User and group lookups through NSS, shared by the generator and the helpers,
+//! so that directory (LDAP, SSSD) and systemd-homed users are known too,
+//! not only the ones of /etc/passwd.
+//!
+//! Inside another root, NSS would answer for the running system instead,
+//! so the passwd and group files of the root are read.
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use users::os::unix::UserExt;
+pub use users::{Group, User};
+
+use crate::sysroot;
+
+static PASSWD_FILE: &str = "/etc/passwd";
+static GROUP_FILE: &str = "/etc/group";
+
+// systemd-homed allocates the UIDs of its users in this range
+const HOMED_UIDS: RangeInclusive<libc::uid_t> = 60001..=60513;
+
+// name, password, uid, gid, gecos, home and shell
+const PASSWD_FIELDS: usize = 7;
+// name, password, gid and members
+const GROUP_FIELDS: usize = 4;
+
+// the users of a passwd file, an image without one has no users
+fn passwd_users(path: &Path) -> Vec<User> {
+    let entries = read_entries(path, PASSWD_FIELDS).unwrap_or_default();
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (uid, gid) = (entry[2].parse().ok()?, entry[3].parse().ok()?);
+            Some(User::new(uid, &entry[0], gid).with_home_dir(&entry[5]).with_shell(&entry[6]))
+        })
+        .collect()
+}
+
+fn group_groups(path: &Path) -> Vec<Group> {
+    let entries = read_entries(path, GROUP_FIELDS).unwrap_or_default();
+    entries
+        .iter()
+        .filter_map(|entry| Some(Group::new(entry[2].parse().ok()?, &entry[0])))
+        .collect()
+}
+
+pub fn user_by_name(name: &str) -> Option<User> {
+    if sysroot::is_set() {
+        return passwd_users(&sysroot::path(PASSWD_FILE))
+            .into_iter()
+            .find(|user| user.name() == name);
+    }
+    users::get_user_by_name(name)
+}
+
+pub fn user_by_uid(uid: libc::uid_t) -> Option<User> {
+    if sysroot::is_set() {
+        return passwd_users(&sysroot::path(PASSWD_FILE)).into_iter().find(|user| user.uid() == uid);
+    }
+    users::get_user_by_uid(uid)
+}
+
+pub fn group_by_name(name: &str) -> Option<Group> {
+    if sysroot::is_set() {
+        return group_groups(&sysroot::path(GROUP_FILE))
+            .into_iter()
+            .find(|group| group.name() == name);
+    }
+    users::get_group_by_name(name)
+}
+
+/// The entries of a file of colon-separated fields, like /etc/passwd or /etc/shadow,
+/// `None` if it can't be read. Blank lines, comments and lines with less than
+/// `fields` fields are skipped, so that one bad line doesn't hide the others.
+pub fn read_entries(path: &Path, fields: usize) -> Option<Vec<Vec<String>>> {
+    let content = fs::read(path).ok()?;
+    let entries = String::from_utf8_lossy(&content)
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').map(ToOwned::to_owned).collect::<Vec<_>>())
+        .filter(|entry| entry.len() >= fields)
+        .collect();
+    Some(entries)
+}
+
+/// Home directory of the user, `None` if it isn't set.
+pub fn home_dir(user: &User) -> Option<&Path> {
+    Some(user.home_dir()).filter(|dir| !dir.as_os_str().is_empty())
+}
+
+/// Whether the home directory of the user is managed by systemd-homed,
+/// which only mounts it while the user is logged in.
+pub fn is_homed(user: &User) -> bool {
+    HOMED_UIDS.contains(&user.uid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_groups, passwd_users, read_entries};
+    use crate::testing::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn root_files() {
+        let dir = temp_dir("identity");
+        let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+        fs::write(
+            &passwd,
+            "root:x:0:0:root:/root:/bin/bash\n\
+             \n\
+             short:x:1000\n\
+             baduid:x:none:100::/home/baduid:/bin/sh\n\
+             alice:x:1000:100:Alice:/home/alice:/bin/sh\n",
+        )
+        .unwrap();
+        fs::write(&group, "root:x:0:\n\n# comment\nusers:x:100:alice\n").unwrap();
+
+        let users = passwd_users(&passwd);
+        let users = users
+            .iter()
+            .map(|user| (user.name(), user.uid(), user.primary_group_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(users, vec![("root", 0, 0), ("alice", 1000, 100)]);
+        let groups = group_groups(&group);
+        let groups = groups.iter().map(|group| (group.name(), group.gid())).collect::<Vec<_>>();
+        assert_eq!(groups, vec![("root", 0), ("users", 100)]);
+
+        // a missing or unreadable file has no users
+        assert!(passwd_users(&dir.join("missing")).is_empty());
+        assert_eq!(read_entries(&dir, 1), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,22 @@
+//! Code shared by the generator and the systemd-cron helpers.
+
+extern crate cronparse;
+extern crate glob;
+extern crate libc;
+extern crate time;
+extern crate users;
+
+pub mod access;
+pub mod calendar;
+pub mod config;
+pub mod dbus;
+pub mod identity;
+pub mod lint;
+pub mod names;
+pub mod schedule;
+pub mod sysroot;
+pub mod systemd;
+pub mod timers;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,168 @@
+//! Checks for crontab settings the generator ignores, or handles differently from cron.
+
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use cronparse::crontab::{CrontabEntry, EnvVarEntry};
+use cronparse::schedule::Period;
+
+use crate::schedule::restricts_both_days;
+use crate::sysroot;
+
+static ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Variables of other cron daemons, which the generated units don't honour.
+static UNSUPPORTED_VARIABLES: [(&str, &str); 5] = [
+    ("MAILFROM", "the sender of failure mails can't be changed"),
+    ("CONTENT_TYPE", "failure mails are always plain text"),
+    ("CONTENT_TRANSFER_ENCODING", "failure mails are always plain text"),
+    ("CRON_CORRECT_DST", "systemd timers always follow daylight saving time changes"),
+    ("MLS_LEVEL", "SELinux is not supported"),
+];
+
+/// An active line of a crontab.
+#[derive(Debug, PartialEq)]
+pub enum Line<T> {
+    /// `NAME=value`, with quotes removed.
+    Variable(String, String),
+    Entry(T),
+}
+
+/// The active lines of a crontab with their numbers, each parsed on its own the same way
+/// `CrontabFile` reads them, so that all errors can be reported at once.
+pub fn parse_lines<T: FromStr>(content: &str) -> impl Iterator<Item = (usize, Result<Line<T>, T::Err>)> + '_ {
+    content
+        .lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let parsed = match line.parse::<EnvVarEntry>() {
+                Ok(EnvVarEntry(name, value)) => Ok(Line::Variable(name, value)),
+                Err(_) => line.parse::<T>().map(Line::Entry),
+            };
+            (index + 1, parsed)
+        })
+}
+
+/// Whether systemd can find the time zone, as used by `CRON_TZ`, inside the root.
+pub fn valid_timezone(timezone: &str) -> bool {
+    let path = Path::new(timezone);
+    !timezone.is_empty() && path.components().all(|c| matches!(c, Component::Normal(_))) && sysroot::path(ZONEINFO_DIR).join(path).is_file()
+}
+
+fn is_flag(value: &str) -> bool {
+    matches!(value, "yes" | "true" | "1" | "no" | "false" | "0" | "")
+}
+
+/// Warnings about a variable set in a crontab.
+pub fn variable_warnings(name: &str, value: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    match name {
+        "DELAY" | "RANDOM_DELAY" | "MAIL_OUTPUT_LIMIT" if value.parse::<u64>().is_err() => {
+            warnings.push(format!("{}={} is not a number, it is ignored", name, value))
+        }
+        "START_HOURS_RANGE" => {
+            let mut hours = value.splitn(2, '-');
+            match hours.next().and_then(|start| start.parse::<u64>().ok()) {
+                Some(start) if start <= 23 => {
+                    if let Some(end) = hours.next() {
+                        warnings.push(format!(
+                            "only the start of START_HOURS_RANGE is used, jobs run at {}:00 but aren't stopped at {}:00",
+                            start, end
+                        ));
+                    }
+                }
+                _ => warnings.push(format!("START_HOURS_RANGE={} has no valid start hour, it is ignored", value)),
+            }
+        }
+        "CRON_TZ" if !valid_timezone(value) => {
+            warnings.push(format!("CRON_TZ={} is not a known time zone, the timers would fail to load", value))
+        }
+        "PERSISTENT" if !is_flag(value) && value != "auto" => {
+            warnings.push(format!("PERSISTENT={} is not yes, no or auto, it means no", value))
+        }
+        "BATCH" if !is_flag(value) => warnings.push(format!("BATCH={} is not yes or no, it means no", value)),
+        _ => (),
+    }
+
+    if let Some(&(_, reason)) = UNSUPPORTED_VARIABLES.iter().find(|&&(n, _)| n == name) {
+        warnings.push(format!("{} is not supported, {}", name, reason));
+    }
+
+    warnings
+}
+
+/// Warnings about a job, whatever its user.
+pub fn entry_warnings(entry: &CrontabEntry) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if let Some(cmd) = entry.command() {
+        // `\%` is the way to get a literal % in cron
+        if cmd.replace("\\%", "").contains('%') {
+            warnings.push(
+                "% in the command is passed to the shell as is, cron would end the command there \
+                 and pass the rest as standard input"
+                    .to_owned(),
+            );
+        }
+    }
+
+    match entry.period() {
+        Some(&Period::Days(days)) if days > 31 => warnings.push(format!(
+            "periods over 31 days are approximated, this job runs every {} months instead of every {} days",
+            days / 30,
+            days
+        )),
+        _ => (),
+    }
+
+    if entry.calendar().map(restricts_both_days).unwrap_or(false) {
+        warnings.push(
+            "both day of month and day of week are restricted, cron runs the job when either matches, \
+             the timer only when both match"
+                .to_owned(),
+        );
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_warnings, parse_lines, variable_warnings, Line};
+    use cronparse::crontab::{CrontabEntry, UserCrontabEntry};
+
+    #[test]
+    fn lines() {
+        let content = "# comment\n\n   \nMAILTO=\"root\"\n0 * * * * true\n 0 * * * * false # indented\nnonsense\n";
+        let lines = parse_lines::<UserCrontabEntry>(content).collect::<Vec<_>>();
+        assert_eq!(lines.iter().map(|&(lineno, _)| lineno).collect::<Vec<_>>(), [4, 5, 6, 7]);
+        assert_eq!(lines[0].1.as_ref().ok(), Some(&Line::Variable("MAILTO".to_owned(), "root".to_owned())));
+        assert!(matches!(lines[1].1, Ok(Line::Entry(_))));
+        assert!(matches!(lines[2].1, Ok(Line::Entry(_))));
+        assert!(lines[3].1.is_err());
+    }
+
+    #[test]
+    fn variables() {
+        assert!(variable_warnings("DELAY", "10").is_empty());
+        assert_eq!(variable_warnings("DELAY", "soon"), ["DELAY=soon is not a number, it is ignored"]);
+        assert_eq!(variable_warnings("START_HOURS_RANGE", "3-22").len(), 1);
+        assert!(variable_warnings("START_HOURS_RANGE", "3").is_empty());
+        assert_eq!(variable_warnings("PERSISTENT", "maybe").len(), 1);
+        assert!(variable_warnings("PERSISTENT", "auto").is_empty());
+        assert_eq!(variable_warnings("MAILFROM", "cron@example.org").len(), 1);
+        assert_eq!(variable_warnings("CRON_TZ", "../etc/passwd").len(), 1);
+        assert!(variable_warnings("SHELL", "/bin/bash").is_empty());
+    }
+
+    #[test]
+    fn entries() {
+        let warnings = |line: &str| entry_warnings(&CrontabEntry::from(line.parse::<UserCrontabEntry>().unwrap())).len();
+        assert_eq!(warnings("0 0 * * * date +\\%F"), 0);
+        assert_eq!(warnings("0 0 * * * date +%F"), 1);
+        assert_eq!(warnings("0 0 13 * fri true"), 1);
+        assert_eq!(warnings("@daily true"), 0);
+    }
+}
@@ -1,69 +1,205 @@
 extern crate cronparse;
 extern crate libc;
 extern crate md5;
-extern crate pgs_files;
+extern crate systemd_crontab_generator;
+extern crate time;
 
 #[macro_use]
 extern crate log;
 extern crate kernlog;
 
+use std::collections::BTreeSet;
 use std::env;
 use std::fs::{create_dir_all, metadata, File};
-use std::io::{self, Error, Write};
+use std::io::{self, Write};
 use std::os::unix::fs::symlink;
 use std::path::Path;
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread::spawn;
 
-use cronparse::crontab::{AnacrontabEntry, SystemCrontabEntry, UserCrontabEntry};
+use cronparse::crontab::{AnacrontabEntry, SystemCrontabEntry};
+use systemd_crontab_generator::config;
+use systemd_crontab_generator::sysroot;
+use systemd_crontab_generator::systemd::Support;
+use systemd_crontab_generator::timers;
 
+mod checks;
+mod cmdline;
 mod generate;
 mod process;
+mod scripts;
+#[cfg(test)]
+mod testing;
 
 include!(concat!(env!("OUT_DIR"), "/config.rs"));
 static SYSTEM_CRONTAB_DIR: &str = "/etc/cron.d"; // SystemCrontabEntry
 static SYSTEM_CRONTAB_FILE: &str = "/etc/crontab";
 static ANACRONTAB_FILE: &str = "/etc/anacrontab"; // AnacrontabEntry
 static REBOOT_FILE: &str = "/run/crond.reboot";
+static INITRD_RELEASE_FILE: &str = "/etc/initrd-release";
+static KERNLOG_LEVEL_ENV: &str = "KERNLOG_LEVEL";
 
-fn main() -> Result<(), Error> {
+// units which couldn't be written make the generator fail, so that systemd
+// reports it, while a broken crontab only loses its own jobs with a warning
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+fn write_failed() {
+    FAILED.store(true, Ordering::SeqCst);
+}
+
+// the timers generated by this run, for remove-stale-stamps to tell
+// the stamps of cron jobs from the ones of other timers
+static TIMERS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn timer_generated(name: &str) {
+    TIMERS.lock().unwrap().insert(name.to_owned());
+}
+
+// the same test as systemd, which only sets $SYSTEMD_IN_INITRD since version 251
+fn in_initrd() -> bool {
+    match env::var("SYSTEMD_IN_INITRD") {
+        Ok(value) => value == "1",
+        Err(_) => Path::new(INITRD_RELEASE_FILE).exists(),
+    }
+}
+
+// kernlog logs everything unless $KERNLOG_LEVEL says otherwise, debug only
+// overrides a lower level
+fn init_logger(debug: bool) {
+    if debug {
+        env::set_var(KERNLOG_LEVEL_ENV, "trace");
+    }
     kernlog::init().unwrap();
+}
 
-    let dest_dir = match env::args().nth(1) {
-        None => {
-            println!("Usage: systemd-crontab-generator <destination-directory>");
+// splits --root <directory> or --root=<directory>, wherever it is, from the
+// directories, None for any other option or a --root without a value
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Option<(Option<String>, Vec<String>)> {
+    let mut root = None;
+    let mut dirs = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--root" {
+            root = Some(args.next()?);
+        } else if let Some(value) = arg.strip_prefix("--root=") {
+            root = Some(value.to_owned());
+        } else if arg.starts_with('-') {
+            return None;
+        } else {
+            dirs.push(arg);
+        }
+    }
+    Some((root, dirs))
+}
+
+fn main() {
+    // systemd passes the normal, early and late directories, a single one is
+    // used for everything when the generator is run by hand
+    let dirs = match parse_args(env::args().skip(1)) {
+        Some((root, dirs)) if dirs.len() == 1 || dirs.len() == 3 => {
+            // image builders generate the units of another root, before it boots
+            if let Some(root) = root {
+                sysroot::set(root);
+            }
+            dirs
+        }
+        _ => {
+            println!("Usage: systemd-crontab-generator [--root <directory>] <normal-dir> [<early-dir> <late-dir>]");
             exit(1);
         }
-        Some(path) => path,
+    };
+    let (normal_dir, early_dir, late_dir) = match &dirs[..] {
+        [dir] => (dir.clone(), dir.clone(), dir.clone()),
+        [normal, early, late] => (normal.clone(), early.clone(), late.clone()),
+        _ => unreachable!(),
     };
 
-    let s = dest_dir.clone();
+    // units baked in the /etc of an image would override the ones generated at boot forever
+    if sysroot::is_set() {
+        let etc = sysroot::path("/etc");
+        let etc = etc.canonicalize().unwrap_or(etc);
+        for dir in &[&normal_dir, &early_dir, &late_dir] {
+            if Path::new(dir).canonicalize().unwrap_or_else(|_| dir.into()).starts_with(&etc) {
+                println!("<3>refusing to write units in {}: they would override the ones generated at boot", dir);
+                exit(1);
+            }
+        }
+    }
+
+    let cmdline_warnings = cmdline::init();
+    init_logger(cmdline::get().debug);
+    for warning in cmdline_warnings {
+        warn!("kernel command line: {}", warning);
+    }
+
+    // the jobs belong to the real root, once the initrd switched to it
+    if !sysroot::is_set() && in_initrd() {
+        debug!("running in the initrd, nothing to generate");
+        return;
+    }
+
+    if !cmdline::get().enabled {
+        info!("cron is disabled on the kernel command line, nothing to generate");
+        if let Err(err) = mask_cron_target(&early_dir) {
+            warn!("error masking cron.target: {}", err);
+            exit(1);
+        }
+        return;
+    }
+
+    for warning in config::init() {
+        warn!("{}: {}", config::path().display(), warning);
+    }
+    match Support::installed() {
+        Some(support) => debug!("generating units for systemd {}", support.version()),
+        None => info!("unknown systemd version, generating units for the build settings"),
+    }
+
+    // the late directory, so that units in /etc and /usr override the generated ones
+    let s = late_dir.clone();
     let user_thread = spawn(move || {
-        if !metadata(USERS_CRONTAB_DIR).map(|m| m.is_dir()).unwrap_or(false) {
-            return generate_after_var_unit(&*s);
+        let statedir = sysroot::path(&config::get().statedir);
+        if !metadata(&statedir).map(|m| m.is_dir()).unwrap_or(false) {
+            if let Err(err) = generate_after_var_unit(&*s) {
+                warn!("error generating cron-after-var.service: {}", err);
+                write_failed();
+            }
+            return;
         }
 
-        process::process_crontab_dir::<UserCrontabEntry, _>(USERS_CRONTAB_DIR, s);
-        create_reboot_lock_file();
-        Ok(())
+        process::process_users_crontab_dir(&statedir, s);
+        // the image boots without it, another run against it must generate the same units
+        if !sysroot::is_set() {
+            create_reboot_lock_file();
+        }
     });
 
-    let s = dest_dir.clone();
+    let s = late_dir.clone();
     let system_thread = spawn(move || {
-        process::process_crontab_file::<SystemCrontabEntry, _, _>(SYSTEM_CRONTAB_FILE, &s);
-        process::process_crontab_dir::<SystemCrontabEntry, _>(SYSTEM_CRONTAB_DIR, &s);
+        process::process_system_crontab_file::<SystemCrontabEntry, _, _>(sysroot::path(SYSTEM_CRONTAB_FILE), &s);
+        process::process_crontab_dir::<SystemCrontabEntry, _>(&sysroot::path(SYSTEM_CRONTAB_DIR), &s);
+        scripts::generate_script_units(Path::new(&s), Path::new(&normal_dir));
     });
 
-    let s = dest_dir;
+    let s = late_dir;
     let anacron_thread = spawn(move || {
-        process::process_crontab_file::<AnacrontabEntry, _, _>(ANACRONTAB_FILE, &s);
+        process::process_system_crontab_file::<AnacrontabEntry, _, _>(sysroot::path(ANACRONTAB_FILE), &s);
     });
 
-    let _ = user_thread.join();
-    let _ = system_thread.join();
-    let _ = anacron_thread.join();
+    for thread in [user_thread, system_thread, anacron_thread] {
+        if thread.join().is_err() {
+            write_failed();
+        }
+    }
 
-    Ok(())
+    if !sysroot::is_set() {
+        record_timers();
+    }
+
+    if FAILED.load(Ordering::SeqCst) {
+        exit(1);
+    }
 }
 
 fn generate_after_var_unit(dest_dir: &str) -> Result<(), io::Error> {
@@ -79,19 +215,67 @@ ConditionDirectoryNotEmpty={statedir}
 
 [Service]
 Type=oneshot
-ExecStart=/bin/sh -c "{bindir}/systemctl daemon-reload ; {bindir}/systemctl try-restart cron.target""###,
-        statedir = USERS_CRONTAB_DIR,
-        bindir = BIN_DIR
+ExecStart={libdir}/{package}/cron-update --try-restart"###,
+        statedir = config::get().statedir,
+        libdir = config::get().libdir,
+        package = PACKAGE
     )?;
 
     let multiuser_wants_path = Path::new(dest_dir).join("multi-user.target.wants");
     create_dir_all(&multiuser_wants_path)?;
-    symlink(cron_after_var_unit_path, multiuser_wants_path.join("cron-after-var.service"))?;
+    symlink(sysroot::strip(&cron_after_var_unit_path), multiuser_wants_path.join("cron-after-var.service"))?;
     Ok(())
 }
 
+// in the early directory, so that it takes precedence over the units of /etc too
+fn mask_cron_target(early_dir: &str) -> Result<(), io::Error> {
+    symlink("/dev/null", Path::new(early_dir).join("cron.target"))
+}
+
 fn create_reboot_lock_file() {
     if let Err(err) = File::create(REBOOT_FILE) {
         warn!("error creating lock file {}: {}", REBOOT_FILE, err);
     }
 }
+
+// the history is kept in /var, which may not be mounted or writable yet,
+// the next runs record the timers then
+fn record_timers() {
+    let generated = TIMERS.lock().unwrap();
+    if let Err(err) = timers::write(Path::new(timers::TIMERS_FILE), &generated) {
+        warn!("error writing {}: {}", timers::TIMERS_FILE, err);
+    }
+
+    let history = Path::new(timers::HISTORY_FILE);
+    if !history.parent().map(Path::is_dir).unwrap_or(false) {
+        debug!("{} isn't available yet, not recording the timers", history.display());
+        return;
+    }
+    match timers::record(history, &generated) {
+        Err(ref err) if err.raw_os_error() == Some(libc::EROFS) => debug!("{} is read-only, not recording the timers", history.display()),
+        Err(err) => warn!("error recording the timers in {}: {}", history.display(), err),
+        Ok(()) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_args;
+
+    fn parse(args: &[&str]) -> Option<(Option<String>, Vec<String>)> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn arguments() {
+        let dirs = |dirs: &[&str]| dirs.iter().map(|dir| dir.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse(&["/run/n", "/run/e", "/run/l"]), Some((None, dirs(&["/run/n", "/run/e", "/run/l"]))));
+        assert_eq!(parse(&["--root", "/img", "/out"]), Some((Some("/img".to_owned()), dirs(&["/out"]))));
+        assert_eq!(parse(&["/out", "--root", "/img"]), Some((Some("/img".to_owned()), dirs(&["/out"]))));
+        assert_eq!(parse(&["/out", "--root=/img"]), Some((Some("/img".to_owned()), dirs(&["/out"]))));
+
+        assert_eq!(parse(&["/out", "--root"]), None);
+        assert_eq!(parse(&["--verbose", "/out"]), None);
+        assert_eq!(parse(&["-r", "/img", "/out"]), None);
+    }
+}
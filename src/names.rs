@@ -0,0 +1,128 @@
+//! Names of the files the generator picks in the cron directories.
+
+use glob::Pattern;
+
+/// Leftovers of package managers and editors, which would duplicate jobs.
+pub static IGNORED_NAMES: [&str; 17] = [
+    "*~",
+    "#*#",
+    "*.swp",
+    "*.bak",
+    "*.dpkg-old",
+    "*.dpkg-dist",
+    "*.dpkg-new",
+    "*.dpkg-tmp",
+    "*.dpkg-bak",
+    "*.ucf-old",
+    "*.ucf-dist",
+    "*.ucf-new",
+    "*.rpmsave",
+    "*.rpmorig",
+    "*.rpmnew",
+    "*.pacsave",
+    "*.pacnew",
+];
+
+// the pattern of `IGNORED_NAMES` matching the file name, if any
+fn leftover_pattern(name: &str) -> Option<&'static str> {
+    IGNORED_NAMES
+        .iter()
+        .find(|pattern| Pattern::new(pattern).map(|p| p.matches(name)).unwrap_or(false))
+        .cloned()
+}
+
+/// Why the generator ignores a file of /etc/cron.d, if it does: the same as Debian's
+/// cron, only run-parts names are loaded, so that any leftover is skipped, even
+/// the ones of `IGNORED_NAMES` which aren't named as such.
+pub fn ignored_by(name: &str) -> Option<String> {
+    match leftover_pattern(name) {
+        Some(pattern) => Some(format!("its name matches {}", pattern)),
+        None if !is_runparts_name(name) => Some("its name has other characters than letters, digits, underscores and hyphens".to_owned()),
+        None => None,
+    }
+}
+
+/// The naming rule of Debian's run-parts for the scripts of /etc/cron.<schedule>:
+/// only letters, digits, underscores and hyphens, so that backups are skipped.
+pub fn is_runparts_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The naming rules of run-parts `--lsbsysinit`: the run-parts names, plus the LSB
+/// hierarchical and reserved namespaces such as `_vendor.pkg-job`, without the
+/// leftovers of package managers.
+pub fn is_lsb_name(name: &str) -> bool {
+    if leftover_pattern(name).is_some() {
+        return false;
+    }
+    if is_runparts_name(name) {
+        return true;
+    }
+
+    // ^_?([a-z0-9_.]+-)+[a-z0-9]+$
+    let name = name.strip_prefix('_').unwrap_or(name);
+    let mut parts = name.rsplitn(2, '-');
+    let last = parts.next().unwrap_or("");
+    let hierarchy = match parts.next() {
+        Some(hierarchy) => hierarchy,
+        None => return false,
+    };
+    let lowercase = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    !last.is_empty()
+        && last.chars().all(lowercase)
+        && hierarchy
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| lowercase(c) || c == '_' || c == '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ignored_by, is_lsb_name, is_runparts_name};
+
+    #[test]
+    fn leftovers() {
+        let cases = [
+            ("backup.dpkg-old", "its name matches *.dpkg-old"),
+            ("backup.dpkg-dist", "its name matches *.dpkg-dist"),
+            ("backup~", "its name matches *~"),
+            ("#backup#", "its name matches #*#"),
+            ("backup.rpmnew", "its name matches *.rpmnew"),
+            ("backup.rpmsave", "its name matches *.rpmsave"),
+            (".backup.swp", "its name matches *.swp"),
+            ("backup.pacnew", "its name matches *.pacnew"),
+            ("backup.orig", "its name has other characters than letters, digits, underscores and hyphens"),
+            ("back up", "its name has other characters than letters, digits, underscores and hyphens"),
+        ];
+        for &(name, reason) in &cases {
+            assert_eq!(ignored_by(name).as_deref(), Some(reason), "{}", name);
+            assert!(!is_runparts_name(name) && !is_lsb_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn runparts_names() {
+        for &name in &["popularity-contest", "0anacron", "logrotate", "sys_stat", "PHP-Sessions"] {
+            assert_eq!(ignored_by(name), None, "{}", name);
+            assert!(is_runparts_name(name) && is_lsb_name(name), "{}", name);
+        }
+        assert!(!is_runparts_name(""));
+    }
+
+    #[test]
+    fn lsb_names() {
+        for &name in &["_vendor.pkg-job", "example.org-backup", "org.debian.pkg-daily2", "_pkg.cron-x"] {
+            assert!(is_lsb_name(name) && !is_runparts_name(name), "{}", name);
+        }
+        for &name in &[
+            "",
+            "pkg.job",
+            "pkg.x-",
+            "Vendor.pkg-job",
+            "pkg.x-Job",
+            "pkg.x--job",
+            "_vendor.pkg-job.dpkg-old",
+        ] {
+            assert!(!is_lsb_name(name), "{}", name);
+        }
+    }
+}
@@ -1,20 +1,23 @@
 use std::collections::BTreeMap;
 use std::convert::AsRef;
 use std::fs::{metadata, read_dir};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use cronparse::crontab::{CrontabEntry, EnvVarEntry};
+use cronparse::crontab::{CrontabEntry, EnvVarEntry, UserCrontabEntry};
 use cronparse::{CrontabFile, CrontabFileError, CrontabFileErrorKind};
 
+use systemd_crontab_generator::access::denied_by;
+use systemd_crontab_generator::config::{self, Config};
+use systemd_crontab_generator::names::ignored_by;
+
+use crate::checks::{check_system_crontab, check_user_crontab};
 use crate::generate::generate_systemd_units;
+use crate::write_failed;
 
-pub fn process_crontab_dir<T: FromStr, D: AsRef<Path>>(srcdir: &str, dstdir: D)
-where
-    CrontabEntry: From<T>,
-    CrontabFileError: From<<T as FromStr>::Err>,
-{
-    let files = read_dir(srcdir).and_then(|fs| {
+fn list_crontab_dir(srcdir: &Path) -> io::Result<Vec<PathBuf>> {
+    read_dir(srcdir).and_then(|fs| {
         fs.map(|r| r.map(|p| p.path()))
             .filter(|r| {
                 r.as_ref()
@@ -25,17 +28,107 @@ where
                     .unwrap_or(true)
             })
             .collect::<Result<Vec<PathBuf>, _>>()
-    });
-    match files {
-        Err(err) => warn!("error processing directory {}: {}", srcdir, err),
+    })
+}
+
+// shell commands which may come with run-parts in the Debian forms
+fn is_runparts_guard(words: &[&str]) -> bool {
+    matches!(words, ["cd", "/"] | ["test", "-x", "/usr/sbin/anacron"] | ["[", "-x", "/usr/sbin/anacron", "]"])
+}
+
+// the run-parts jobs of Debian's /etc/crontab and /etc/anacrontab, such as
+// `test -x /usr/sbin/anacron || ( cd / && run-parts --report /etc/cron.daily )`,
+// returns the schedule whose native unit already runs the same directory;
+// a job running anything else is left alone
+fn runparts_schedule<'a>(command: &str, config: &'a Config) -> Option<&'a str> {
+    let mut schedule = None;
+
+    for part in command.split(|c| ";&|".contains(c)) {
+        let words = part
+            .split(|c: char| c.is_whitespace() || "(){}".contains(c))
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        if words.is_empty() || is_runparts_guard(&words) {
+            continue;
+        }
+
+        let words = match words.split_first() {
+            Some((&"nice", rest)) => rest,
+            _ => &words[..],
+        };
+        match words.split_first() {
+            Some((name, args)) if *name == "run-parts" || name.ends_with("/run-parts") => {
+                let dirs = args.iter().filter(|arg| !arg.starts_with('-')).collect::<Vec<_>>();
+                let found = match dirs[..] {
+                    [dir] => config
+                        .schedules
+                        .iter()
+                        .find(|schedule| dir.trim_end_matches('/') == format!("{}/cron.{}", config.confdir, schedule)),
+                    _ => None,
+                };
+                match (found, schedule) {
+                    (Some(found), None) => schedule = Some(&**found),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    schedule
+}
+
+pub fn process_crontab_dir<T: FromStr, D: AsRef<Path>>(srcdir: &Path, dstdir: D)
+where
+    CrontabEntry: From<T>,
+    CrontabFileError: From<<T as FromStr>::Err>,
+{
+    match list_crontab_dir(srcdir) {
+        Err(err) => warn!("error processing directory {}: {}", srcdir.display(), err),
         Ok(files) => {
             for file in files {
-                process_crontab_file::<T, _, _>(file, dstdir.as_ref());
+                match file.file_name().and_then(|n| n.to_str()).and_then(ignored_by) {
+                    Some(reason) => warn!("ignoring {}: {}", file.display(), reason),
+                    None => process_system_crontab_file::<T, _, _>(file, dstdir.as_ref()),
+                }
             }
         }
     }
 }
 
+// spool crontabs are named after their owner, skip those
+// of users not allowed to use cron anymore
+pub fn process_users_crontab_dir<D: AsRef<Path>>(srcdir: &Path, dstdir: D) {
+    match list_crontab_dir(srcdir) {
+        Err(err) => warn!("error processing directory {}: {}", srcdir.display(), err),
+        Ok(files) => {
+            for file in files {
+                let user = file.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                match denied_by(user) {
+                    Ok(None) => match check_user_crontab(&file, user) {
+                        Ok(()) => process_crontab_file::<UserCrontabEntry, _, _>(&file, dstdir.as_ref()),
+                        Err(reason) => warn!("skipping crontab {}: {}", file.display(), reason),
+                    },
+                    Ok(Some(rule)) => warn!("skipping crontab {}: user {} is not allowed by {}", file.display(), user, rule),
+                    Err(err) => warn!("skipping crontab {}: error checking access of user {}: {}", file.display(), user, err),
+                }
+            }
+        }
+    }
+}
+
+// a missing file is reported by process_crontab_file
+pub fn process_system_crontab_file<T: FromStr, P: AsRef<Path>, D: AsRef<Path>>(path: P, dstdir: D)
+where
+    CrontabEntry: From<T>,
+    CrontabFileError: From<<T as FromStr>::Err>,
+{
+    match check_system_crontab(path.as_ref()) {
+        Err(ref reason) if metadata(path.as_ref()).is_ok() => warn!("skipping crontab {}: {}", path.as_ref().display(), reason),
+        _ => process_crontab_file::<T, _, _>(path, dstdir),
+    }
+}
+
 pub fn process_crontab_file<T: FromStr, P: AsRef<Path>, D: AsRef<Path>>(path: P, dstdir: D)
 where
     CrontabEntry: From<T>,
@@ -49,9 +142,21 @@ where
                     Ok(CrontabEntry::EnvVar(EnvVarEntry(name, value))) => {
                         env.insert(name, value);
                     }
-                    Ok(data) => match generate_systemd_units(data, &env, path.as_ref(), dstdir.as_ref()) {
-                        Ok(_) => (),
-                        Err(err) => warn!("error generating unit from {}: {}", path.as_ref().display(), err),
+                    Ok(data) => match data.command().and_then(|command| runparts_schedule(command, config::get())) {
+                        // the scripts would run twice, users can't run them as root anyway
+                        Some(schedule) if !matches!(data, CrontabEntry::User(_)) => info!(
+                            "skipping job of {}: cron-{}.timer already runs {}",
+                            path.as_ref().display(),
+                            schedule,
+                            data.command().unwrap_or("")
+                        ),
+                        _ => match generate_systemd_units(data, &env, path.as_ref(), dstdir.as_ref()) {
+                            Ok(_) => (),
+                            Err(err) => {
+                                warn!("error generating unit from {}: {}", path.as_ref().display(), err);
+                                write_failed();
+                            }
+                        },
                     },
                     Err(
                         err
@@ -76,3 +181,53 @@ where
             warn!("error parsing file {}: {}", path.as_ref().display(), err);
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::runparts_schedule;
+    use systemd_crontab_generator::config::Config;
+
+    fn config(schedules: &[&str]) -> Config {
+        Config {
+            confdir: "/etc".to_owned(),
+            schedules: schedules.iter().map(|&s| s.to_owned()).collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn debian_runparts_jobs() {
+        let debian = config(&["hourly", "daily", "weekly", "monthly"]);
+        let schedule = |command| runparts_schedule(command, &debian);
+
+        // /etc/crontab
+        assert_eq!(schedule("cd / && run-parts --report /etc/cron.hourly"), Some("hourly"));
+        assert_eq!(schedule("test -x /usr/sbin/anacron || ( cd / && run-parts --report /etc/cron.daily )"), Some("daily"));
+        assert_eq!(schedule("test -x /usr/sbin/anacron || ( cd / && run-parts --report /etc/cron.weekly )"), Some("weekly"));
+        assert_eq!(schedule("test -x /usr/sbin/anacron || { cd / && run-parts --report /etc/cron.monthly; }"), Some("monthly"));
+        // /etc/anacrontab
+        assert_eq!(schedule("nice run-parts --report /etc/cron.daily"), Some("daily"));
+        assert_eq!(schedule("/bin/run-parts /etc/cron.weekly/"), Some("weekly"));
+        assert_eq!(schedule("[ -x /usr/sbin/anacron ] || run-parts /etc/cron.daily"), Some("daily"));
+    }
+
+    #[test]
+    fn other_jobs() {
+        let debian = config(&["hourly", "daily", "weekly", "monthly"]);
+        let schedule = |command| runparts_schedule(command, &debian);
+
+        assert_eq!(schedule("cd /tmp && run-parts --report /etc/cron.daily"), None);
+        assert_eq!(schedule("run-parts /etc/cron.daily; rm -f /tmp/lock"), None);
+        assert_eq!(schedule("backup && run-parts /etc/cron.daily"), None);
+        assert_eq!(schedule("run-parts /etc/cron.daily /etc/cron.weekly"), None);
+        assert_eq!(schedule("run-parts /etc/cron.daily && run-parts /etc/cron.weekly"), None);
+        assert_eq!(schedule("run-parts /etc/cron.daily && run-parts /etc/cron.daily"), None);
+        assert_eq!(schedule("run-parts /etc/cron.custom"), None);
+        assert_eq!(schedule("run-parts /srv/cron.daily"), None);
+        assert_eq!(schedule("run-parts --report"), None);
+        assert_eq!(schedule("cd /"), None);
+
+        // the scripts of disabled schedules only run through the crontab
+        assert_eq!(runparts_schedule("run-parts /etc/cron.daily", &config(&["hourly"])), None);
+    }
+}
@@ -0,0 +1,379 @@
+//! Translation of crontab schedules into systemd calendar events.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use cronparse::crontab::CrontabEntry;
+use cronparse::interval::Interval;
+use cronparse::schedule::{Calendar, Period};
+use cronparse::Limited;
+
+static MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+static DAY_OF_WEEK_NAMES: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Job environment variables which change the generated calendar events.
+pub struct Options<'a> {
+    /// Minutes to add to the scheduled time, from `DELAY`.
+    pub delay: u64,
+    /// Hour to run daily and longer jobs at, from `START_HOURS_RANGE`.
+    pub hour: u64,
+    /// Time zone of the events, from `CRON_TZ`, empty for local time.
+    pub timezone: &'a str,
+}
+
+impl<'a> Options<'a> {
+    pub fn from_env(env: &'a BTreeMap<String, String>) -> Options<'a> {
+        Options {
+            delay: env.get("DELAY").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0),
+            hour: env
+                .get("START_HOURS_RANGE")
+                .and_then(|v| v.split('-').next().and_then(|v| v.parse::<u64>().ok()))
+                .unwrap_or(0),
+            timezone: env.get("CRON_TZ").map(|v| &**v).unwrap_or(""),
+        }
+    }
+}
+
+/// Calendar event of the timer generated for the entry, `None` for `@reboot` jobs.
+pub fn entry_event(entry: &CrontabEntry, options: &Options) -> Option<String> {
+    entry
+        .period()
+        .and_then(|period| period_event(period, options))
+        .or_else(|| entry.calendar().map(|cal| calendar_event(cal, options)))
+}
+
+pub fn period_event(period: &Period, options: &Options) -> Option<String> {
+    let Options { delay, hour, timezone } = *options;
+
+    match *period {
+        Period::Reboot => None,
+        Period::Minutely => Some("minutely".to_owned()),
+        Period::Hourly => {
+            if delay == 0 {
+                Some("hourly".to_owned())
+            } else {
+                Some(format!("*-*-* *:{}:0 {}", delay, timezone))
+            }
+        }
+        Period::Midnight => {
+            if delay == 0 {
+                Some("daily".to_owned())
+            } else {
+                Some(format!("*-*-* 0:{}:0 {}", delay, timezone))
+            }
+        }
+        Period::Daily => {
+            if delay == 0 && hour == 0 {
+                Some("daily".to_owned())
+            } else {
+                Some(format!("*-*-* {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Weekly => {
+            if delay == 0 && hour == 0 {
+                Some("weekly".to_owned())
+            } else {
+                Some(format!("Mon *-*-* {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Monthly => {
+            if delay == 0 && hour == 0 {
+                Some("monthly".to_owned())
+            } else {
+                Some(format!("*-*-1 {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Quaterly => {
+            if delay == 0 && hour == 0 {
+                Some("quarterly".to_owned())
+            } else {
+                Some(format!("*-1,4,7,10-1 {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Biannually => {
+            if delay == 0 && hour == 0 {
+                Some("semiannually".to_owned())
+            } else {
+                Some(format!("*-1,7-1 {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Yearly => {
+            if delay == 0 && hour == 0 {
+                Some("yearly".to_owned())
+            } else {
+                Some(format!("*-1-1 {}:{}:0 {}", hour, delay, timezone))
+            }
+        }
+        Period::Days(days) => {
+            // workaround for anacrontab
+            if days > 31 {
+                Some(format!("*-1/{}-1 {}:{}:0 {}", days / 30, hour, delay, timezone))
+            } else {
+                Some(format!("*-*-1/{} {}:{}:0 {}", days, hour, delay, timezone))
+            }
+        }
+    }
+}
+
+pub fn calendar_event(cal: &Calendar, options: &Options) -> String {
+    let Calendar {
+        ref dows,
+        ref days,
+        ref mons,
+        ref hrs,
+        ref mins,
+    } = *cal;
+
+    format!(
+        "{} *-{}-{} {}:{}:00 {}",
+        linearize(&**dows, "", ToString::to_string),
+        linearize(&**mons, "*", |&mon| (mon as u8).to_string()),
+        linearize(&**days, "*", ToString::to_string),
+        linearize(&**hrs, "*", ToString::to_string),
+        linearize(&**mins, "*", ToString::to_string),
+        options.timezone,
+    )
+}
+
+/// Cron runs a job restricting both days of month and days of week when either matches,
+/// while the generated calendar event requires both to match.
+pub fn restricts_both_days(cal: &Calendar) -> bool {
+    !is_full(&*cal.days) && !is_full(&*cal.dows)
+}
+
+pub fn describe_period(period: &Period) -> String {
+    match *period {
+        Period::Reboot => "once after boot".to_owned(),
+        Period::Minutely => "every minute".to_owned(),
+        Period::Hourly => "every hour".to_owned(),
+        Period::Midnight | Period::Daily => "every day".to_owned(),
+        Period::Weekly => "every week".to_owned(),
+        Period::Monthly => "every month".to_owned(),
+        Period::Quaterly => "every quarter".to_owned(),
+        Period::Biannually => "every half year".to_owned(),
+        Period::Yearly => "every year".to_owned(),
+        Period::Days(days) => format!("every {} days", days),
+    }
+}
+
+/// Plain English description of a crontab calendar, as the generated timer understands it.
+pub fn describe_calendar(cal: &Calendar) -> String {
+    let mut output = match (single_value(&*cal.mins), single_value(&*cal.hrs)) {
+        (Some(min), Some(hour)) => format!("at {:02}:{:02}", hour.0, min.0),
+        _ => format!(
+            "at {} past {}",
+            describe_intervals(&*cal.mins, "minute", ToString::to_string),
+            describe_intervals(&*cal.hrs, "hour", ToString::to_string)
+        ),
+    };
+
+    if !is_full(&*cal.days) {
+        output.push_str(" on ");
+        output.push_str(&*describe_intervals(&*cal.days, "day-of-month", ToString::to_string));
+    }
+
+    if !is_full(&*cal.dows) {
+        output.push_str(if is_full(&*cal.days) { " on " } else { " if it is also " });
+        output.push_str(&*describe_intervals(&*cal.dows, "day-of-week", |&dow| DAY_OF_WEEK_NAMES[dow as usize].to_owned()));
+    }
+
+    if !is_full(&*cal.mons) {
+        output.push_str(" in ");
+        output.push_str(&*describe_intervals(&*cal.mons, "month", |&mon| MONTH_NAMES[mon as usize - 1].to_owned()));
+    }
+
+    output
+}
+
+fn is_full<T: Limited>(input: &[Interval<T>]) -> bool {
+    input.contains(&Interval::Full(1))
+}
+
+fn single_value<T: Limited>(input: &[Interval<T>]) -> Option<T> {
+    match *input {
+        [Interval::Value(value)] => Some(value),
+        _ => None,
+    }
+}
+
+fn describe_intervals<T, C>(input: &[Interval<T>], unit: &str, name: C) -> String
+where
+    T: Limited,
+    C: Fn(&T) -> String,
+{
+    let mut values = Vec::new();
+    let mut parts = Vec::new();
+
+    for interval in input {
+        match *interval {
+            Interval::Value(value) => values.push(name(&value)),
+            Interval::Full(1) => parts.push(format!("every {}", unit)),
+            Interval::Full(step) => parts.push(format!("every {} {}", ordinal(step), unit)),
+            Interval::Range(from, to, 1) => parts.push(format!("every {} from {} through {}", unit, name(&from), name(&to))),
+            Interval::Range(from, to, step) => {
+                parts.push(format!("every {} {} from {} through {}", ordinal(step), unit, name(&from), name(&to)))
+            }
+        }
+    }
+
+    if !values.is_empty() {
+        parts.insert(0, format!("{} {}", unit, join_list(&values)));
+    }
+
+    join_list(&parts)
+}
+
+fn join_list(items: &[String]) -> String {
+    match items.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+fn ordinal(n: u8) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+// cronparse's own iterator saturates at the upper bound, so that `*/7` minutes
+// would also yield 59, step one by one to stop at the last real value instead
+fn interval_values<T: Limited>(interval: &Interval<T>) -> Vec<T> {
+    let (from, to, step) = match *interval {
+        Interval::Value(value) => return vec![value],
+        Interval::Range(from, to, step) => (from, to, step),
+        Interval::Full(step) => (T::min_value(), T::max_value(), step),
+    };
+
+    let mut output = Vec::new();
+    let mut value = from;
+    'outer: while value <= to {
+        output.push(value);
+        for _ in 0..step.max(1) {
+            if value >= to {
+                break 'outer;
+            }
+            value = value + 1;
+        }
+    }
+    output
+}
+
+fn linearize<T, C>(input: &[Interval<T>], star: &str, conv: C) -> String
+where
+    T: Limited,
+    C: Fn(&T) -> String,
+{
+    if input.len() == 1 && input[0] == Interval::Full(1) {
+        star.to_owned()
+    } else {
+        let mut output = String::new();
+        for part in input.iter().flat_map(interval_values).collect::<BTreeSet<_>>().iter() {
+            output.push_str(&*conv(part));
+            output.push(',');
+        }
+        output.pop();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calendar_event, describe_calendar, period_event, restricts_both_days, Options};
+    use cronparse::schedule::{Calendar, Period};
+    use std::collections::BTreeMap;
+
+    fn calendar(schedule: &str) -> Calendar {
+        schedule.parse().unwrap()
+    }
+
+    fn event(schedule: &str) -> String {
+        let env = BTreeMap::new();
+        calendar_event(&calendar(schedule), &Options::from_env(&env))
+    }
+
+    #[test]
+    fn options() {
+        let mut env = BTreeMap::new();
+        env.insert("DELAY".to_owned(), "5".to_owned());
+        env.insert("START_HOURS_RANGE".to_owned(), "3-22".to_owned());
+        env.insert("CRON_TZ".to_owned(), "Europe/Paris".to_owned());
+        let options = Options::from_env(&env);
+        assert_eq!((options.delay, options.hour, options.timezone), (5, 3, "Europe/Paris"));
+
+        env.insert("DELAY".to_owned(), "soon".to_owned());
+        env.remove("START_HOURS_RANGE");
+        let options = Options::from_env(&env);
+        assert_eq!((options.delay, options.hour), (0, 0));
+    }
+
+    #[test]
+    fn periods() {
+        let env = BTreeMap::new();
+        let plain = Options::from_env(&env);
+        let shifted = Options {
+            delay: 5,
+            hour: 3,
+            timezone: "UTC",
+        };
+        let cases = [
+            (Period::Reboot, None, None),
+            (Period::Minutely, Some("minutely"), Some("minutely")),
+            (Period::Hourly, Some("hourly"), Some("*-*-* *:5:0 UTC")),
+            (Period::Midnight, Some("daily"), Some("*-*-* 0:5:0 UTC")),
+            (Period::Daily, Some("daily"), Some("*-*-* 3:5:0 UTC")),
+            (Period::Weekly, Some("weekly"), Some("Mon *-*-* 3:5:0 UTC")),
+            (Period::Monthly, Some("monthly"), Some("*-*-1 3:5:0 UTC")),
+            (Period::Quaterly, Some("quarterly"), Some("*-1,4,7,10-1 3:5:0 UTC")),
+            (Period::Biannually, Some("semiannually"), Some("*-1,7-1 3:5:0 UTC")),
+            (Period::Yearly, Some("yearly"), Some("*-1-1 3:5:0 UTC")),
+            (Period::Days(3), Some("*-*-1/3 0:0:0 "), Some("*-*-1/3 3:5:0 UTC")),
+            (Period::Days(60), Some("*-1/2-1 0:0:0 "), Some("*-1/2-1 3:5:0 UTC")),
+        ];
+        for &(ref period, plain_event, shifted_event) in &cases {
+            assert_eq!(period_event(period, &plain).as_deref(), plain_event, "{:?}", period);
+            assert_eq!(period_event(period, &shifted).as_deref(), shifted_event, "{:?}", period);
+        }
+    }
+
+    #[test]
+    fn calendars() {
+        assert_eq!(event("* * * * *"), " *-*-* *:*:00 ");
+        assert_eq!(event("30 4 1,15 * 5"), "Fri *-*-1,15 4:30:00 ");
+        assert_eq!(event("0 9-17/4 * jan-mar,dec mon-fri"), "Mon,Tue,Wed,Thu,Fri *-1,2,3,12-* 9,13,17:0:00 ");
+        // steps stop at the last real value, 59 isn't a multiple of 7
+        assert_eq!(event("*/7 * * * *"), " *-*-* *:0,7,14,21,28,35,42,49,56:00 ");
+        assert_eq!(event("*/20 */12 */10 */6 *"), " *-1,7-1,11,21,31 0,12:0,20,40:00 ");
+        // overlapping lists are merged and sorted
+        assert_eq!(event("5,1-3,2 * * * *"), " *-*-* *:1,2,3,5:00 ");
+    }
+
+    #[test]
+    fn days_of_month_and_week() {
+        assert!(restricts_both_days(&calendar("0 0 13 * fri")));
+        assert!(!restricts_both_days(&calendar("0 0 13 * *")));
+        assert!(!restricts_both_days(&calendar("0 0 * * fri")));
+        assert_eq!(describe_calendar(&calendar("0 0 13 * fri")), "at 00:00 on day-of-month 13 if it is also day-of-week Friday");
+        assert_eq!(describe_calendar(&calendar("0 0 * * sat,sun")), "at 00:00 on day-of-week Saturday and Sunday");
+        assert_eq!(describe_calendar(&calendar("*/15 9-17 * 6 *")), "at every 15th minute past every hour from 9 through 17 in month June");
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs::{create_dir_all, metadata, read_dir, File};
+use std::io::{self, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use systemd_crontab_generator::config::{self, Config};
+use systemd_crontab_generator::names::is_runparts_name;
+use systemd_crontab_generator::sysroot;
+
+use super::{PACKAGE, SCHEDULES};
+use crate::checks::check_system_file;
+use crate::write_failed;
+
+// boot scripts must only run once per boot, which cron-boot.service takes care of
+static RUN_PARTS_SCHEDULES: [&str; 1] = ["boot"];
+
+// executable files named with the run-parts rules, in the order run-parts runs them
+fn list_scripts(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut scripts = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if !is_runparts_name(name) {
+            debug!("ignoring {}: not a run-parts name", path.display());
+            continue;
+        }
+        match metadata(&path) {
+            Ok(ref meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => scripts.push(path),
+            _ => debug!("ignoring {}: not an executable file", path.display()),
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+/// One service per script of /etc/cron.<schedule>, instead of cron-<schedule>.service
+/// running them all with run-parts, so that each script gets its own status, logs
+/// and failure notification.
+///
+/// cron-<schedule>.service is masked in `mask_dir`, which must take precedence
+/// over the installed unit, while the other units may go to a lower one.
+pub fn generate_script_units(dstdir: &Path, mask_dir: &Path) {
+    generate_schedules_units(SCHEDULES, config::get(), 0, dstdir, mask_dir)
+}
+
+// the units of the schedules built in, by the configuration, for the scripts owned by `owner`
+fn generate_schedules_units(schedules: &[&str], config: &Config, owner: libc::uid_t, dstdir: &Path, mask_dir: &Path) {
+    for schedule in schedules.iter() {
+        // the timer still starts the target, which has nothing left to run
+        if !config.schedules.iter().any(|s| s == schedule) {
+            info!("skipping the scripts of cron.{}: the schedule is disabled", schedule);
+            if let Err(err) = symlink("/dev/null", mask_dir.join(format!("cron-{}.service", schedule))) {
+                warn!("error masking cron-{}.service: {}", schedule, err);
+                write_failed();
+            }
+            continue;
+        }
+        if RUN_PARTS_SCHEDULES.contains(schedule) {
+            continue;
+        }
+
+        let dir = sysroot::path(Path::new(&config.confdir).join(format!("cron.{}", schedule)));
+        match list_scripts(&dir) {
+            Ok(ref scripts) if scripts.is_empty() => (),
+            Ok(scripts) => {
+                if let Err(err) = generate_schedule_units(schedule, &scripts, owner, dstdir, mask_dir) {
+                    warn!("error generating units for {}: {}", dir.display(), err);
+                    write_failed();
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => warn!("error processing directory {}: {}", dir.display(), err),
+        }
+    }
+}
+
+fn generate_schedule_units(schedule: &str, scripts: &[PathBuf], owner: libc::uid_t, dstdir: &Path, mask_dir: &Path) -> io::Result<()> {
+    let target_wants_path = dstdir.join(format!("cron-{}.target.wants", schedule));
+    create_dir_all(&target_wants_path)?;
+
+    let mut previous: Option<String> = None;
+    for script in scripts {
+        // scripts run as root, the same as system crontabs
+        if let Err(reason) = check_system_file(script, owner) {
+            warn!("skipping script {}: {}", script.display(), reason);
+            continue;
+        }
+
+        let name = script.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let source_path = sysroot::strip(script);
+        let service_unit_name = format!("cron-{}-{}.service", schedule, name);
+        let service_unit_path = dstdir.join(&service_unit_name);
+
+        debug!("generating service {:?} from {:?}", service_unit_path, script);
+        {
+            let mut service_unit_file = File::create(&service_unit_path)?;
+
+            writeln!(
+                service_unit_file,
+                r###"[Unit]
+Description={package} {schedule} script {name}
+Documentation=man:systemd.cron(7)
+PartOf=cron-{schedule}.target
+RefuseManualStart=true
+RefuseManualStop=true
+SourcePath={script}
+OnFailure=cron-failure@%i.service"###,
+                package = PACKAGE,
+                schedule = schedule,
+                name = name,
+                script = source_path.display(),
+            )?;
+
+            // one after the other, like run-parts, but a failure doesn't stop the next ones
+            if let Some(ref previous) = previous {
+                writeln!(service_unit_file, "After={}", previous)?;
+            }
+
+            writeln!(
+                service_unit_file,
+                r###"
+[Service]
+Type=oneshot
+IgnoreSIGPIPE=false
+ExecStart={script}"###,
+                script = source_path.display(),
+            )?;
+        }
+
+        symlink(sysroot::strip(&service_unit_path), target_wants_path.join(&service_unit_name))?;
+        previous = Some(service_unit_name);
+    }
+
+    // the scripts must not run twice
+    symlink("/dev/null", mask_dir.join(format!("cron-{}.service", schedule)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_schedules_units, PACKAGE};
+    use crate::testing::temp_dir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use systemd_crontab_generator::config::Config;
+
+    fn script(dir: &Path, name: &str, mode: u32) {
+        let path = dir.join(name);
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn script_units() {
+        let root = temp_dir("scripts");
+        let (confdir, dstdir, mask_dir) = (root.join("etc"), root.join("late"), root.join("normal"));
+        for dir in &[
+            confdir.join("cron.daily"),
+            confdir.join("cron.boot"),
+            confdir.join("cron.weekly"),
+            dstdir.clone(),
+            mask_dir.clone(),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let daily = confdir.join("cron.daily");
+        script(&daily, "50-b", 0o755);
+        script(&daily, "10-a", 0o755);
+        script(&daily, "30-c", 0o700);
+        script(&daily, "20-noexec", 0o644);
+        script(&daily, "40-d.dpkg-old", 0o755);
+        script(&daily, "60-writable", 0o777);
+        fs::create_dir(daily.join("70-dir")).unwrap();
+        script(&confdir.join("cron.boot"), "10-boot", 0o755);
+        script(&confdir.join("cron.weekly"), "10-weekly", 0o755);
+
+        let config = Config {
+            confdir: confdir.display().to_string(),
+            schedules: vec!["boot".to_owned(), "daily".to_owned(), "hourly".to_owned()],
+            ..Config::default()
+        };
+        // the scripts of the user running the tests stand for the ones of root
+        let owner = unsafe { libc::geteuid() };
+        generate_schedules_units(&["boot", "hourly", "daily", "weekly"], &config, owner, &dstdir, &mask_dir);
+
+        // in the order of run-parts, each one after the previous
+        let mut units = fs::read_dir(&dstdir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        units.sort();
+        assert_eq!(
+            units,
+            vec![
+                "cron-daily-10-a.service",
+                "cron-daily-30-c.service",
+                "cron-daily-50-b.service",
+                "cron-daily.target.wants"
+            ]
+        );
+        assert_eq!(
+            read(dstdir.join("cron-daily-10-a.service")),
+            format!(
+                "[Unit]\n\
+                 Description={package} daily script 10-a\n\
+                 Documentation=man:systemd.cron(7)\n\
+                 PartOf=cron-daily.target\n\
+                 RefuseManualStart=true\n\
+                 RefuseManualStop=true\n\
+                 SourcePath={daily}/10-a\n\
+                 OnFailure=cron-failure@%i.service\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 IgnoreSIGPIPE=false\n\
+                 ExecStart={daily}/10-a\n",
+                package = PACKAGE,
+                daily = daily.display()
+            )
+        );
+        assert!(read(dstdir.join("cron-daily-30-c.service")).contains("\nAfter=cron-daily-10-a.service\n"));
+        assert!(read(dstdir.join("cron-daily-50-b.service")).contains("\nAfter=cron-daily-30-c.service\n"));
+
+        for unit in &["cron-daily-10-a.service", "cron-daily-30-c.service", "cron-daily-50-b.service"] {
+            let link = dstdir.join("cron-daily.target.wants").join(unit);
+            assert_eq!(fs::read_link(link).unwrap(), dstdir.join(unit));
+        }
+
+        // run-parts keeps running the boot scripts, disabled schedules run nothing
+        let mut masks = fs::read_dir(&mask_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        masks.sort();
+        assert_eq!(masks, vec![mask_dir.join("cron-daily.service"), mask_dir.join("cron-weekly.service")]);
+        for mask in &masks {
+            assert_eq!(fs::read_link(mask).unwrap(), Path::new("/dev/null"));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+//! The root directory of the system whose crontabs are read, `/` unless `--root`
+//! or `$SYSTEMD_CRON_ROOT` point at a mounted image or a chroot.
+//!
+//! Every input path, user lookup and state file is taken inside the root, while
+//! the paths written in the generated units stay the ones of the system itself.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub static ROOT_ENV: &str = "SYSTEMD_CRON_ROOT";
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+// setuid and setgid programs, such as crontab, must not trust the environment
+fn is_privileged() -> bool {
+    unsafe { libc::getuid() != libc::geteuid() || libc::getgid() != libc::getegid() }
+}
+
+/// Set the root, before anything is read. Returns false if it was already in use.
+pub fn set<P: AsRef<Path>>(dir: P) -> bool {
+    ROOT.set(dir.as_ref().to_owned()).is_ok()
+}
+
+/// The root directory, from `$SYSTEMD_CRON_ROOT` unless `set` was called first.
+pub fn get() -> &'static Path {
+    ROOT.get_or_init(|| match env::var_os(ROOT_ENV) {
+        Some(ref dir) if !dir.is_empty() && !is_privileged() => PathBuf::from(dir),
+        _ => PathBuf::from("/"),
+    })
+}
+
+/// Whether an image or a chroot is used instead of the running system.
+pub fn is_set() -> bool {
+    get() != Path::new("/")
+}
+
+/// A path of the system, inside the root.
+pub fn path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    match path.strip_prefix("/") {
+        Ok(relative) if is_set() => get().join(relative),
+        _ => path.to_owned(),
+    }
+}
+
+/// A path inside the root, as the system sees it, e.g. for the generated units.
+pub fn strip<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    match path.strip_prefix(get()) {
+        Ok(relative) if is_set() => Path::new("/").join(relative),
+        _ => path.to_owned(),
+    }
+}
@@ -0,0 +1,191 @@
+//! Access to the systemd manager (org.freedesktop.systemd1) over D-Bus,
+//! and the features of the installed systemd.
+
+use std::io;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::dbus::{Connection, Value};
+use crate::sysroot;
+
+static DESTINATION: &str = "org.freedesktop.systemd1";
+static MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+static MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+static PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+pub static UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+pub static SERVICE_INTERFACE: &str = "org.freedesktop.systemd1.Service";
+
+static SUPPORT: OnceLock<Option<Support>> = OnceLock::new();
+
+/// What the installed systemd understands, by version.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Support {
+    version: u32,
+}
+
+impl Support {
+    pub fn new(version: u32) -> Support {
+        Support { version }
+    }
+
+    /// From the first line of `systemctl --version`, e.g. `systemd 252 (252.22-1)`,
+    /// which works without a running manager, as in generators.
+    pub fn detect() -> Option<Support> {
+        let output = Command::new("systemctl").arg("--version").output().ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut words = stdout.lines().next()?.split_whitespace();
+        match (words.next(), words.next().and_then(|v| v.parse().ok())) {
+            (Some("systemd"), Some(version)) => Some(Support::new(version)),
+            _ => None,
+        }
+    }
+
+    /// The installed systemd, detected once, unknown inside another root
+    /// whose systemd may not be the running one.
+    pub fn installed() -> Option<Support> {
+        *SUPPORT.get_or_init(|| if sysroot::is_set() { None } else { Support::detect() })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// `Persistent=` in timers.
+    pub fn persistent(&self) -> bool {
+        self.version >= 212
+    }
+
+    /// `RandomizedDelaySec=` in timers, `AccuracySec=` is the fallback.
+    pub fn randomized_delay(&self) -> bool {
+        self.version >= 229
+    }
+
+    /// Whether `OnCalendar=` understands the shorthand, such as `daily`.
+    pub fn calendar_shorthand(&self, name: &str) -> bool {
+        match name {
+            "hourly" | "daily" | "weekly" | "monthly" => true,
+            "yearly" | "annually" => self.version >= 209,
+            "minutely" | "quarterly" | "semiannually" => self.version >= 217,
+            _ => false,
+        }
+    }
+}
+
+pub struct Systemd {
+    conn: Connection,
+}
+
+impl Systemd {
+    pub fn connect() -> io::Result<Systemd> {
+        Connection::system().map(|conn| Systemd { conn })
+    }
+
+    fn manager_call(&mut self, member: &str, args: &[Value]) -> io::Result<Vec<Value>> {
+        self.conn.call(DESTINATION, MANAGER_PATH, MANAGER_INTERFACE, member, args)
+    }
+
+    /// Object path of a unit, loading it if needed.
+    pub fn unit_path(&mut self, unit: &str) -> io::Result<String> {
+        self.manager_call("LoadUnit", &[Value::Str(unit.to_owned())])?
+            .first()
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "LoadUnit returned no object path"))
+    }
+
+    /// Read a property of a unit, e.g. `(SERVICE_INTERFACE, "ExecMainStatus")`.
+    pub fn unit_property(&mut self, unit: &str, interface: &str, property: &str) -> io::Result<Value> {
+        let path = self.unit_path(unit)?;
+        let mut reply = self.conn.call(
+            DESTINATION,
+            &*path,
+            PROPERTIES_INTERFACE,
+            "Get",
+            &[Value::Str(interface.to_owned()), Value::Str(property.to_owned())],
+        )?;
+
+        match reply.pop() {
+            Some(Value::Variant(value)) => Ok(*value),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("no value for property {}", property))),
+        }
+    }
+
+    /// Same as `systemctl daemon-reload`, returns once the reload is finished.
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.manager_call("Reload", &[]).map(|_| ())
+    }
+
+    /// Queue a restart job for the unit, returns the job object path.
+    pub fn restart_unit(&mut self, unit: &str, mode: &str) -> io::Result<String> {
+        self.job_call("RestartUnit", unit, mode)
+    }
+
+    /// Queue a restart job for the unit if it is running, returns the job object path.
+    pub fn try_restart_unit(&mut self, unit: &str, mode: &str) -> io::Result<String> {
+        self.job_call("TryRestartUnit", unit, mode)
+    }
+
+    fn job_call(&mut self, member: &str, unit: &str, mode: &str) -> io::Result<String> {
+        let reply = self.manager_call(member, &[Value::Str(unit.to_owned()), Value::Str(mode.to_owned())])?;
+        Ok(reply.first().and_then(Value::as_str).unwrap_or("").to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Support, Systemd, DESTINATION, SERVICE_INTERFACE, UNIT_INTERFACE};
+    use crate::dbus::testing::{serve, PrivateBus};
+    use crate::dbus::{Connection, Value};
+
+    #[test]
+    fn support() {
+        assert!(!Support::new(211).persistent() && Support::new(212).persistent());
+        assert!(!Support::new(228).randomized_delay() && Support::new(229).randomized_delay());
+    }
+
+    // answers like systemd for a single cron-foo.service
+    fn mock_systemd(member: &str, args: &[Value]) -> Result<Vec<Value>, String> {
+        let arg = |n: usize| args.get(n).and_then(Value::as_str).unwrap_or("");
+        let value = |v: Value| Ok(vec![Value::Variant(Box::new(v))]);
+        match (member, arg(0), arg(1)) {
+            ("LoadUnit", "cron-foo.service", _) => {
+                Ok(vec![Value::ObjectPath("/org/freedesktop/systemd1/unit/cron_2dfoo_2eservice".to_owned())])
+            }
+            ("LoadUnit", _, _) => Err("org.freedesktop.systemd1.NoSuchUnit".to_owned()),
+            ("Get", "org.freedesktop.systemd1.Service", "User") => value(Value::Str("alice".to_owned())),
+            ("Get", "org.freedesktop.systemd1.Service", "ExecMainStatus") => value(Value::Int32(3)),
+            ("Get", "org.freedesktop.systemd1.Unit", "InvocationID") => value(Value::Array(vec![Value::Byte(0xab), Value::Byte(1)])),
+            ("Get", _, _) => Err("org.freedesktop.DBus.Error.UnknownProperty".to_owned()),
+            ("Reload", _, _) => Ok(vec![]),
+            ("TryRestartUnit", "cron-foo.service", "replace") => Ok(vec![Value::ObjectPath("/org/freedesktop/systemd1/job/42".to_owned())]),
+            _ => Err("org.freedesktop.DBus.Error.UnknownMethod".to_owned()),
+        }
+    }
+
+    // needs dbus-daemon, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn manager_calls() {
+        let bus = PrivateBus::start().unwrap();
+        serve(&*bus.address, DESTINATION, mock_systemd).unwrap();
+        let mut systemd = Systemd {
+            conn: Connection::open(&*bus.address).unwrap(),
+        };
+
+        let user = systemd.unit_property("cron-foo.service", SERVICE_INTERFACE, "User").unwrap();
+        assert_eq!(user.as_str(), Some("alice"));
+        let status = systemd
+            .unit_property("cron-foo.service", SERVICE_INTERFACE, "ExecMainStatus")
+            .unwrap();
+        assert_eq!(status.as_i64(), Some(3));
+        let id = systemd.unit_property("cron-foo.service", UNIT_INTERFACE, "InvocationID").unwrap();
+        assert_eq!(id.as_bytes(), Some(vec![0xab, 1]));
+
+        assert!(systemd.unit_property("cron-foo.service", UNIT_INTERFACE, "Nonsense").is_err());
+        assert!(systemd.unit_property("cron-bar.service", SERVICE_INTERFACE, "User").is_err());
+
+        systemd.reload().unwrap();
+        assert_eq!(systemd.try_restart_unit("cron-foo.service", "replace").unwrap(), "/org/freedesktop/systemd1/job/42");
+    }
+}
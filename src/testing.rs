@@ -0,0 +1,15 @@
+//! Fixtures shared by the tests of the library and of the binaries, which
+//! include this file with `#[path]` as they can't see the library's tests.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// An empty directory for the test `name`, which each test removes when done.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("systemd-cron-{}.{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
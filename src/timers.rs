@@ -0,0 +1,82 @@
+//! The timers the generator ever wrote, so that remove-stale-stamps can tell the
+//! stamps of cron jobs from the ones of other timers, whatever their names.
+//!
+//! The generator adds the timers of each run to the history, which is only pruned
+//! by remove-stale-stamps, once a timer has neither a unit nor a stamp anymore.
+
+use std::collections::BTreeSet;
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// The timers of the last run of the generator, also kept when the history
+/// can't be written, such as at boot with a read-only root.
+pub static TIMERS_FILE: &str = "/run/crond.timers";
+
+/// The timers of every run of the generator whose stamps aren't removed yet.
+pub static HISTORY_FILE: &str = "/var/lib/systemd-cron/timers";
+
+// one timer name per line
+fn parse(content: &str) -> BTreeSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn format(timers: &BTreeSet<String>) -> String {
+    timers.iter().map(|timer| format!("{}\n", timer)).collect()
+}
+
+/// Read a list of timers, empty if the file doesn't exist.
+pub fn read(path: &Path) -> io::Result<BTreeSet<String>> {
+    match read_to_string(path) {
+        Ok(content) => Ok(parse(&content)),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Replace a list of timers.
+pub fn write(path: &Path, timers: &BTreeSet<String>) -> io::Result<()> {
+    File::create(path)?.write_all(format(timers).as_bytes())
+}
+
+/// The history, locked against the other runs of the generator and
+/// remove-stale-stamps until it is dropped.
+pub struct History {
+    file: File,
+    pub timers: BTreeSet<String>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> io::Result<History> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        Ok(History {
+            file,
+            timers: parse(&content),
+        })
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(format(&self.timers).as_bytes())
+    }
+}
+
+/// Add the timers of a run of the generator to the history.
+pub fn record(path: &Path, timers: &BTreeSet<String>) -> io::Result<()> {
+    let mut history = History::open(path)?;
+    history.timers.extend(timers.iter().cloned());
+    history.save()
+}